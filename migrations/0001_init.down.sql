@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS path;
+DROP TABLE IF EXISTS segment;
+DROP TABLE IF EXISTS request;
+DROP TABLE IF EXISTS trip;
+DROP TABLE IF EXISTS station;
@@ -0,0 +1,32 @@
+CREATE TABLE IF NOT EXISTS station (
+    id UUID PRIMARY KEY,
+    address TEXT NOT NULL,
+    coords POINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS trip (
+    id UUID PRIMARY KEY,
+    source UUID REFERENCES station (id) NOT NULL,
+    destination UUID REFERENCES station (id) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS request (
+    id UUID PRIMARY KEY,
+    trip_id UUID REFERENCES trip (id),
+    source UUID REFERENCES station (id) NOT NULL,
+    destination UUID REFERENCES station (id) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS segment (
+    s1 UUID REFERENCES station (id) NOT NULL,
+    s2 UUID REFERENCES station (id) NOT NULL,
+    points POINT[] NOT NULL,
+    distance INTEGER NOT NULL,
+    time INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS path (
+    trip_id UUID REFERENCES trip (id) NOT NULL,
+    station_id UUID REFERENCES station (id) NOT NULL,
+    index INTEGER NOT NULL
+);
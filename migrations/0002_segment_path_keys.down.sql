@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS request_destination_idx;
+DROP INDEX IF EXISTS request_source_idx;
+DROP INDEX IF EXISTS request_trip_id_idx;
+DROP INDEX IF EXISTS path_station_id_idx;
+DROP INDEX IF EXISTS segment_s2_idx;
+
+ALTER TABLE path DROP CONSTRAINT IF EXISTS path_pkey;
+ALTER TABLE segment DROP CONSTRAINT IF EXISTS segment_pkey;
@@ -0,0 +1,29 @@
+-- Duplicates left by concurrent inserts are dropped, keeping the newest row.
+DELETE FROM segment
+WHERE ctid IN (
+    SELECT ctid
+    FROM (
+        SELECT ctid, row_number() OVER (PARTITION BY s1, s2 ORDER BY ctid DESC) AS n
+        FROM segment
+    ) ranked
+    WHERE n > 1
+);
+
+DELETE FROM path
+WHERE ctid IN (
+    SELECT ctid
+    FROM (
+        SELECT ctid, row_number() OVER (PARTITION BY trip_id, index ORDER BY ctid DESC) AS n
+        FROM path
+    ) ranked
+    WHERE n > 1
+);
+
+ALTER TABLE segment ADD PRIMARY KEY (s1, s2);
+ALTER TABLE path ADD PRIMARY KEY (trip_id, index);
+
+CREATE INDEX IF NOT EXISTS segment_s2_idx ON segment (s2);
+CREATE INDEX IF NOT EXISTS path_station_id_idx ON path (station_id);
+CREATE INDEX IF NOT EXISTS request_trip_id_idx ON request (trip_id);
+CREATE INDEX IF NOT EXISTS request_source_idx ON request (source);
+CREATE INDEX IF NOT EXISTS request_destination_idx ON request (destination);
@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS trip_merge;
+
+DROP INDEX IF EXISTS trip_parent_trip_id_idx;
+
+ALTER TABLE trip DROP COLUMN IF EXISTS version;
+ALTER TABLE trip DROP COLUMN IF EXISTS parent_trip_id;
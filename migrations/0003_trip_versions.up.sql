@@ -0,0 +1,12 @@
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS parent_trip_id UUID REFERENCES trip (id);
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
+
+CREATE UNIQUE INDEX IF NOT EXISTS trip_parent_trip_id_idx ON trip (parent_trip_id);
+
+CREATE TABLE IF NOT EXISTS trip_merge (
+    trip_id UUID REFERENCES trip (id) NOT NULL,
+    request_id UUID REFERENCES request (id) NOT NULL,
+    PRIMARY KEY (trip_id, request_id)
+);
+
+CREATE INDEX IF NOT EXISTS trip_merge_request_id_idx ON trip_merge (request_id);
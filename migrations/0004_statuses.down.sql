@@ -0,0 +1,2 @@
+ALTER TABLE request DROP COLUMN IF EXISTS status;
+ALTER TABLE trip DROP COLUMN IF EXISTS status;
@@ -0,0 +1,9 @@
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'draft'
+    CHECK (status IN ('draft', 'planned', 'in_progress', 'completed', 'cancelled'));
+
+ALTER TABLE request ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'draft'
+    CHECK (status IN ('draft', 'planned', 'in_progress', 'completed', 'cancelled'));
+
+-- Everything created before statuses existed was already in use.
+UPDATE trip SET status = 'planned';
+UPDATE request SET status = 'planned' WHERE trip_id IS NOT NULL;
@@ -0,0 +1,9 @@
+ALTER TABLE request DROP CONSTRAINT IF EXISTS request_dropoff_window_check;
+ALTER TABLE request DROP CONSTRAINT IF EXISTS request_pickup_window_check;
+
+ALTER TABLE request DROP COLUMN IF EXISTS dropoff_latest;
+ALTER TABLE request DROP COLUMN IF EXISTS dropoff_earliest;
+ALTER TABLE request DROP COLUMN IF EXISTS pickup_latest;
+ALTER TABLE request DROP COLUMN IF EXISTS pickup_earliest;
+
+ALTER TABLE trip DROP COLUMN IF EXISTS departure;
@@ -0,0 +1,12 @@
+-- Times are seconds since the Unix epoch.
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS departure BIGINT;
+
+ALTER TABLE request ADD COLUMN IF NOT EXISTS pickup_earliest BIGINT;
+ALTER TABLE request ADD COLUMN IF NOT EXISTS pickup_latest BIGINT;
+ALTER TABLE request ADD COLUMN IF NOT EXISTS dropoff_earliest BIGINT;
+ALTER TABLE request ADD COLUMN IF NOT EXISTS dropoff_latest BIGINT;
+
+ALTER TABLE request ADD CONSTRAINT request_pickup_window_check
+    CHECK (pickup_earliest <= pickup_latest);
+ALTER TABLE request ADD CONSTRAINT request_dropoff_window_check
+    CHECK (dropoff_earliest <= dropoff_latest);
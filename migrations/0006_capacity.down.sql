@@ -0,0 +1,7 @@
+ALTER TABLE trip DROP COLUMN IF EXISTS capacity_pallets;
+ALTER TABLE trip DROP COLUMN IF EXISTS capacity_volume;
+ALTER TABLE trip DROP COLUMN IF EXISTS capacity_weight;
+
+ALTER TABLE request DROP COLUMN IF EXISTS pallets;
+ALTER TABLE request DROP COLUMN IF EXISTS volume;
+ALTER TABLE request DROP COLUMN IF EXISTS weight;
@@ -0,0 +1,15 @@
+-- Weight in kilograms, volume in cubic meters.
+ALTER TABLE request ADD COLUMN IF NOT EXISTS weight DOUBLE PRECISION NOT NULL DEFAULT 0
+    CHECK (weight >= 0);
+ALTER TABLE request ADD COLUMN IF NOT EXISTS volume DOUBLE PRECISION NOT NULL DEFAULT 0
+    CHECK (volume >= 0);
+ALTER TABLE request ADD COLUMN IF NOT EXISTS pallets INTEGER NOT NULL DEFAULT 0
+    CHECK (pallets >= 0);
+
+-- NULL means unlimited.
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS capacity_weight DOUBLE PRECISION
+    CHECK (capacity_weight >= 0);
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS capacity_volume DOUBLE PRECISION
+    CHECK (capacity_volume >= 0);
+ALTER TABLE trip ADD COLUMN IF NOT EXISTS capacity_pallets INTEGER
+    CHECK (capacity_pallets >= 0);
@@ -0,0 +1,7 @@
+UPDATE segment SET points = ARRAY(
+    SELECT point(p[1], p[0])
+    FROM unnest(points) WITH ORDINALITY AS u(p, i)
+    ORDER BY i
+);
+
+UPDATE station SET coords = point(coords[1], coords[0]);
@@ -0,0 +1,9 @@
+-- Points were stored as (lat, lon). Store them as (lon, lat), the x/y order
+-- PostGIS and most tools expect.
+UPDATE station SET coords = point(coords[1], coords[0]);
+
+UPDATE segment SET points = ARRAY(
+    SELECT point(p[1], p[0])
+    FROM unnest(points) WITH ORDINALITY AS u(p, i)
+    ORDER BY i
+);
@@ -0,0 +1,28 @@
+-- Optional PostGIS storage: geography copies of station locations and
+-- segment polylines, kept up to date by Postgres, with GiST indexes.
+-- Not a versioned migration: the service applies it on every start with
+-- STORAGE=postgis, so every statement has to be safe to repeat. Fails where
+-- the postgis extension is not installed.
+CREATE EXTENSION IF NOT EXISTS postgis;
+
+-- Points are stored as (lon, lat), see 0007_coords_lon_lat.
+CREATE OR REPLACE FUNCTION points_to_line(points POINT[]) RETURNS geography
+LANGUAGE sql IMMUTABLE STRICT
+AS $fn$
+    SELECT CASE WHEN cardinality(points) < 2 THEN NULL ELSE
+        ST_SetSRID(ST_MakeLine(ARRAY(
+            SELECT ST_MakePoint(p[0], p[1])
+            FROM unnest(points) WITH ORDINALITY AS u(p, i)
+            ORDER BY i
+        )), 4326)::geography
+    END;
+$fn$;
+
+ALTER TABLE station ADD COLUMN IF NOT EXISTS location geography(Point, 4326)
+    GENERATED ALWAYS AS (ST_SetSRID(ST_MakePoint(coords[0], coords[1]), 4326)::geography) STORED;
+
+ALTER TABLE segment ADD COLUMN IF NOT EXISTS line geography(LineString, 4326)
+    GENERATED ALWAYS AS (points_to_line(points)) STORED;
+
+CREATE INDEX IF NOT EXISTS station_location_idx ON station USING GIST (location);
+CREATE INDEX IF NOT EXISTS segment_line_idx ON segment USING GIST (line);
@@ -1,17 +1,77 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use anyhow::anyhow;
 use reqwest::Url;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
+use super::error::Error;
 use super::types::*;
 
+/// How many characters of an error response body are kept in [`Error::Status`].
+const MAX_ERROR_BODY: usize = 512;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Timeout for a single HTTP attempt.
+    pub timeout: Duration,
+    /// How many times an idempotent call is retried after the first attempt.
+    pub retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub backoff: Duration,
+    /// Consecutive failed calls after which the circuit opens.
+    pub breaker_threshold: u32,
+    /// How long an open circuit fails fast before a trial call is let through.
+    pub breaker_cooldown: Duration,
+    /// How many calls a single batch of segment fetches may run at once.
+    pub concurrency: usize,
+    /// Whether routes through several stations are requested in a single call
+    /// instead of one call per pair of stations.
+    pub multi_stop: bool,
+    /// Order of latitude and longitude in stops sent and ways received.
+    pub axis_order: AxisOrder,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            backoff: Duration::from_millis(200),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(30),
+            concurrency: 8,
+            multi_stop: false,
+            axis_order: AxisOrder::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
 #[derive(Clone)]
 pub struct Client {
     inner: reqwest::Client,
     base: Url,
+    config: ClientConfig,
+    breaker: Arc<Mutex<Breaker>>,
 }
 
 impl Client {
     pub fn new(base: &str) -> anyhow::Result<Self> {
-        let client = reqwest::Client::new();
+        Self::with_config(base, ClientConfig::default())
+    }
+
+    pub fn with_config(base: &str, config: ClientConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| anyhow!("error building http client: {e}"))?;
         let base = base
             .parse()
             .map_err(|e| anyhow!("{} is not a valid url: {}", base, e))?;
@@ -19,17 +79,231 @@ impl Client {
         Ok(Self {
             inner: client,
             base,
+            config,
+            breaker: Default::default(),
         })
     }
 
-    pub async fn create_route(&self, r: CreateRouteRequest) -> anyhow::Result<CreateRouteResponse> {
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Route calculation has no side effects, so it is retried.
+    pub async fn create_route(&self, r: CreateRouteRequest) -> Result<CreateRouteResponse, Error> {
+        self.call("/api/create_route", &r, true).await
+    }
+
+    /// Same as [`Client::create_route`], but also returns totals for every leg
+    /// between consecutive stops.
+    pub async fn create_multi_route(
+        &self,
+        r: CreateRouteRequest,
+    ) -> Result<CreateMultiRouteResponse, Error> {
+        self.call("/api/create_route", &r, true).await
+    }
+
+    async fn call<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &Req,
+        idempotent: bool,
+    ) -> Result<Resp, Error> {
         let url = self
             .base
-            .join("/api/create_route")
-            .map_err(|e| anyhow!("error joining url: {e}"))?;
+            .join(path)
+            .map_err(|e| Error::Transport(format!("error joining url: {e}")))?;
+
+        self.check_breaker()?;
+
+        let retries = if idempotent { self.config.retries } else { 0 };
+        let mut backoff = self.config.backoff;
+        let mut attempt = 0;
+
+        let result = loop {
+            let result = self.send(url.clone(), request).await;
+
+            match result {
+                Err(e) if e.is_retryable() && attempt < retries => {
+                    attempt += 1;
+                    log::warn!(
+                        "map service call to {path} failed ({e}), retry {attempt}/{retries}"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => break result,
+            }
+        };
+
+        self.record(&result);
+        result
+    }
+
+    async fn send<Req: Serialize, Resp: DeserializeOwned>(
+        &self,
+        url: Url,
+        request: &Req,
+    ) -> Result<Resp, Error> {
+        let response = self.inner.post(url).json(request).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let body = body.chars().take(MAX_ERROR_BODY).collect();
+
+            return Err(Error::Status {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+
+    fn check_breaker(&self) -> Result<(), Error> {
+        let mut breaker = self.breaker.lock().unwrap();
+
+        match breaker.open_until {
+            Some(until) if Instant::now() < until => Err(Error::CircuitOpen),
+            Some(_) => {
+                // Half-open: let this call through as a trial, and keep the
+                // circuit open for everyone else until it finishes.
+                breaker.open_until = Some(Instant::now() + self.config.breaker_cooldown);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, result: &Result<T, Error>) {
+        let mut breaker = self.breaker.lock().unwrap();
+
+        match result {
+            Err(e) if e.is_retryable() => {
+                breaker.failures += 1;
+
+                if breaker.failures >= self.config.breaker_threshold {
+                    if breaker.open_until.is_none() {
+                        log::error!(
+                            "map service failed {} times in a row, failing fast for {:?}",
+                            breaker.failures,
+                            self.config.breaker_cooldown
+                        );
+                    }
+                    breaker.open_until = Some(Instant::now() + self.config.breaker_cooldown);
+                }
+            }
+            _ => *breaker = Breaker::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(50);
+
+    fn client() -> Client {
+        Client::with_config(
+            "http://localhost",
+            ClientConfig {
+                breaker_threshold: 3,
+                breaker_cooldown: COOLDOWN,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    fn failed(status: u16) -> Result<(), Error> {
+        Err(Error::Status {
+            status,
+            body: String::new(),
+        })
+    }
+
+    fn is_open(client: &Client) -> bool {
+        matches!(client.check_breaker(), Err(Error::CircuitOpen))
+    }
+
+    #[test]
+    fn opens_after_threshold() {
+        let client = client();
+
+        client.record(&failed(503));
+        client.record(&failed(503));
+        assert!(!is_open(&client));
+
+        client.record(&failed(503));
+        assert!(is_open(&client));
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let client = client();
+
+        client.record(&failed(503));
+        client.record(&failed(503));
+        client.record(&Ok(()));
+        client.record(&failed(503));
+        client.record(&failed(503));
+
+        assert!(!is_open(&client));
+    }
+
+    #[test]
+    fn client_errors_do_not_open() {
+        let client = client();
+
+        for _ in 0..5 {
+            client.record(&failed(404));
+        }
+
+        assert!(!is_open(&client));
+    }
+
+    #[test]
+    fn half_open_lets_one_trial_through() {
+        let client = client();
+
+        for _ in 0..3 {
+            client.record(&failed(503));
+        }
+        std::thread::sleep(COOLDOWN);
+
+        assert!(client.check_breaker().is_ok());
+        assert!(is_open(&client));
+    }
+
+    #[test]
+    fn successful_trial_closes() {
+        let client = client();
+
+        for _ in 0..3 {
+            client.record(&failed(503));
+        }
+        std::thread::sleep(COOLDOWN);
+
+        assert!(client.check_breaker().is_ok());
+        client.record(&Ok(()));
+
+        assert!(!is_open(&client));
+        assert!(!is_open(&client));
+    }
+
+    #[test]
+    fn failed_trial_opens_again() {
+        let client = client();
+
+        for _ in 0..3 {
+            client.record(&failed(503));
+        }
+        std::thread::sleep(COOLDOWN);
 
-        let response = self.inner.post(url).json(&r).send().await?.json().await?;
+        assert!(client.check_breaker().is_ok());
+        client.record(&failed(503));
 
-        Ok(response)
+        assert!(is_open(&client));
     }
 }
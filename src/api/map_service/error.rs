@@ -0,0 +1,80 @@
+/// Errors returned by the map service client.
+#[derive(Debug)]
+pub enum Error {
+    /// The request did not complete within the configured timeout.
+    Timeout,
+    /// The request could not be sent or the connection failed.
+    Transport(String),
+    /// The map service answered with a non-2xx status.
+    Status { status: u16, body: String },
+    /// The map service answered 2xx, but the body could not be decoded.
+    Body(String),
+    /// Recent calls failed and the client is failing fast.
+    CircuitOpen,
+}
+
+impl Error {
+    /// Whether the failure is likely transient and the call may be retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Timeout | Error::Transport(_) => true,
+            Error::Status { status, .. } => *status >= 500 || *status == 429,
+            Error::Body(_) | Error::CircuitOpen => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Timeout => write!(f, "request timed out"),
+            Error::Transport(e) => write!(f, "transport error: {e}"),
+            Error::Status { status, body } => write!(f, "status {status}: {body}"),
+            Error::Body(e) => write!(f, "malformed response body: {e}"),
+            Error::CircuitOpen => write!(f, "circuit open, map service is unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        if value.is_timeout() {
+            Error::Timeout
+        } else if value.is_decode() {
+            Error::Body(value.to_string())
+        } else {
+            Error::Transport(value.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16) -> Error {
+        Error::Status {
+            status,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_retryable() {
+        assert!(Error::Timeout.is_retryable());
+        assert!(Error::Transport("connection reset".to_string()).is_retryable());
+        assert!(status(500).is_retryable());
+        assert!(status(503).is_retryable());
+        assert!(status(429).is_retryable());
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retryable() {
+        assert!(!status(400).is_retryable());
+        assert!(!status(404).is_retryable());
+        assert!(!Error::Body("expected value".to_string()).is_retryable());
+        assert!(!Error::CircuitOpen.is_retryable());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod client;
+pub mod error;
 pub mod types;
 
-pub use client::Client;
-pub use types::{CreateRouteRequest, CreateRouteResponse};
+pub use client::{Client, ClientConfig};
+pub use error::Error;
+pub use types::{AxisOrder, CreateMultiRouteResponse, CreateRouteRequest, CreateRouteResponse};
@@ -1,5 +1,45 @@
 use serde::{Deserialize, Serialize};
 
+use crate::types::Coord;
+
+/// Order of the two numbers of a point in map service payloads.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum AxisOrder {
+    #[default]
+    LatLon,
+    LonLat,
+}
+
+impl AxisOrder {
+    pub fn encode(self, c: Coord) -> [f64; 2] {
+        match self {
+            AxisOrder::LatLon => [c.lat, c.lon],
+            AxisOrder::LonLat => [c.lon, c.lat],
+        }
+    }
+
+    pub fn decode(self, p: [f64; 2]) -> Coord {
+        match self {
+            AxisOrder::LatLon => Coord::new(p[0], p[1]),
+            AxisOrder::LonLat => Coord::new(p[1], p[0]),
+        }
+    }
+}
+
+impl std::str::FromStr for AxisOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lat_lon" => Ok(AxisOrder::LatLon),
+            "lon_lat" => Ok(AxisOrder::LonLat),
+            _ => Err(format!(
+                "unknown axis order {s}, expected lat_lon or lon_lat"
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CreateRouteRequest {
     pub stops: Vec<[f64; 2]>,
@@ -11,3 +51,124 @@ pub struct CreateRouteResponse {
     pub distance: f64,
     pub duration: f64,
 }
+
+/// Route through more than two stops, with totals for every leg between
+/// consecutive stops.
+#[derive(Serialize, Deserialize)]
+pub struct CreateMultiRouteResponse {
+    pub way: Vec<[f64; 2]>,
+    pub distance: f64,
+    pub duration: f64,
+    pub legs: Vec<RouteLeg>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RouteLeg {
+    pub distance: f64,
+    pub duration: f64,
+}
+
+impl CreateMultiRouteResponse {
+    /// Splits the route into one route per leg, cutting the way at the points
+    /// closest to the intermediate `stops`. Each cut is searched for after the
+    /// previous one and no further than where the leg distances put the next
+    /// stop, so a stop the way passes again later is cut at its own visit.
+    /// Returns `None` if the number of legs does not match the stops.
+    pub fn into_legs(self, stops: &[[f64; 2]]) -> Option<Vec<CreateRouteResponse>> {
+        if stops.len() < 2 || self.legs.len() != stops.len() - 1 || self.way.is_empty() {
+            return None;
+        }
+
+        let sq_distance =
+            |a: &[f64; 2], b: &[f64; 2]| (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2);
+
+        // Share of the way travelled at every point, and at the end of every leg.
+        let along: Vec<f64> = std::iter::once(0.0)
+            .chain(self.way.windows(2).scan(0.0, |length, pair| {
+                *length += sq_distance(&pair[0], &pair[1]).sqrt();
+                Some(*length)
+            }))
+            .collect();
+        let ends: Vec<f64> = self
+            .legs
+            .iter()
+            .scan(0.0, |length, leg| {
+                *length += leg.distance;
+                Some(*length)
+            })
+            .collect();
+        let (way_length, legs_length) = (along[along.len() - 1], ends[ends.len() - 1]);
+
+        let mut cuts = vec![0];
+        for (i, stop) in stops[1..stops.len() - 1].iter().enumerate() {
+            let from = *cuts.last().unwrap();
+            let to = if way_length > 0.0 && legs_length > 0.0 {
+                let next = ends[i + 1] / legs_length * way_length;
+                along
+                    .partition_point(|length| *length <= next)
+                    .max(from + 1)
+            } else {
+                self.way.len()
+            };
+
+            let (cut, _) = self.way[from..to]
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| sq_distance(a, stop).total_cmp(&sq_distance(b, stop)))
+                .unwrap();
+            cuts.push(from + cut);
+        }
+        cuts.push(self.way.len() - 1);
+
+        let legs = cuts
+            .windows(2)
+            .zip(self.legs)
+            .map(|(cut, leg)| CreateRouteResponse {
+                way: self.way[cut[0]..=cut[1]].to_vec(),
+                distance: leg.distance,
+                duration: leg.duration,
+            })
+            .collect();
+
+        Some(legs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(distance: f64) -> RouteLeg {
+        RouteLeg {
+            distance,
+            duration: distance,
+        }
+    }
+
+    #[test]
+    fn repeated_stop_is_cut_at_each_visit() {
+        // Out along one road and back along another that passes closer to
+        // the off-road stop visited on both ways.
+        let off_road = [1.0, 0.15];
+        let route = CreateMultiRouteResponse {
+            way: vec![[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [1.0, 0.2], [0.0, 0.2]],
+            distance: 4.0,
+            duration: 4.0,
+            legs: vec![leg(1.0), leg(1.0), leg(1.0), leg(1.0)],
+        };
+        let stops = [[0.0, 0.0], off_road, [2.0, 0.0], off_road, [0.0, 0.2]];
+
+        let legs = route.into_legs(&stops).unwrap();
+        let ways: Vec<_> = legs.into_iter().map(|leg| leg.way).collect();
+
+        assert_eq!(
+            ways,
+            [
+                vec![[0.0, 0.0], [1.0, 0.0]],
+                vec![[1.0, 0.0], [2.0, 0.0]],
+                vec![[2.0, 0.0], [1.0, 0.2]],
+                vec![[1.0, 0.2], [0.0, 0.2]],
+            ]
+        );
+    }
+}
@@ -1,98 +1,149 @@
-use axum::extract::{Json, Path, State};
-use sqlx::postgres::types::PgPoint;
+use axum::extract::State;
+use axum::http::StatusCode;
 use uuid::Uuid;
 
 use crate::api::map_service;
+use crate::types::Coord;
 
+use super::extract::{Json, Path, Query};
 use super::types::*;
-
-pub type Result<T> = std::result::Result<T, ErrorResponse>;
+use super::validation::{self, Validate};
+use super::{Error, paths, planner, segments, spatial, stations};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Source station, destination station and the segment between them.
+type SegmentRow = (
+    Uuid,
+    String,
+    Coord,
+    Uuid,
+    String,
+    Coord,
+    Option<i32>,
+    Option<i32>,
+);
+
+/// Trip, parent trip, version, status and the cargo requests merged into it.
+type TripVersionRow = (Uuid, Option<Uuid>, i32, String, Vec<Uuid>);
+
+/// Departure and capacity of a trip.
+type TripRow = (Option<i64>, Option<f64>, Option<f64>, Option<i32>);
+
+/// Cargo request, its stations, its pickup and drop-off windows and its cargo.
+type RequestRow = (
+    Uuid,
+    Uuid,
+    Uuid,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    f64,
+    f64,
+    i32,
+);
 
 async fn create_route(
     client: &map_service::Client,
     pool: &sqlx::PgPool,
-    from: &Station,
-    to: &Station,
+    r: &CreateRouteRequest,
     is_request: bool,
-) -> Result<Uuid> {
-    let mut tx = pool
-        .begin()
-        .await
-        .map_err(|e| ErrorResponse::new(format!("error starting transaction: {e}")))?;
+) -> Result<(Uuid, Vec<ErrorDetail>)> {
+    validation::check_route(r, is_request)?;
+
+    let (resolved, warnings) = stations::resolve(
+        pool,
+        &[
+            ("fromStation", &r.from_station),
+            ("toStation", &r.to_station),
+        ],
+        r.on_station_conflict,
+    )
+    .await?;
 
-    for station in [from, to] {
-        let station_id: Option<uuid::Uuid> = sqlx::query_scalar("SELECT id FROM station WHERE id = $1;")
-        .bind(&station.id)
-        .fetch_optional(&mut *tx)
-        .await?;
+    let (from, to) = (&resolved[0].station, &resolved[1].station);
+    let stations = [from, to].map(|station| (station.id, station.coords));
 
-        if station_id.is_none() {
-            sqlx::query(
-                "INSERT INTO station (id, address, coords)
-                VALUES ($1, $2, $3);",
-            )
-            .bind(station.id)
-            .bind(&station.address)
-            .bind(PgPoint {
-                x: station.coords.lat,
-                y: station.coords.lon,
-            })
-            .execute(&mut *tx)
-            .await?;
+    let fetched = if resolved.iter().any(stations::Resolved::moves) {
+        let mut fetched = stations::refetch(pool, client, &resolved).await?;
+
+        // The cached segment between them is deleted with the old coordinates.
+        if !fetched
+            .iter()
+            .any(|seg| (seg.s1, seg.s2) == (from.id, to.id))
+        {
+            let (route, failures) = segments::fetch(client, vec![(stations[0], stations[1])]).await;
+
+            if !failures.is_empty() {
+                return Err(Error::SegmentsUnavailable(failures));
+            }
+
+            fetched.extend(route);
         }
-    }
 
-    let query = if is_request {
-        "INSERT INTO request (id, source, destination)
-        VALUES (gen_random_uuid(), $1, $2)
-        RETURNING id;"
+        fetched
     } else {
-        "INSERT INTO trip (id, source, destination)
-        VALUES (gen_random_uuid(), $1, $2)
-        RETURNING id;"
+        segments::acquire(pool, client, &stations).await?
     };
 
-    let id: Uuid = sqlx::query_scalar(query)
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    for station in &resolved {
+        stations::write(&mut tx, station).await?;
+    }
+
+    stations::check_refetched(&mut tx, &resolved, &fetched).await?;
+
+    let from_window = r.from_window.unwrap_or_default();
+    let to_window = r.to_window.unwrap_or_default();
+    let cargo = r.cargo.unwrap_or_default();
+    let capacity = r.capacity.unwrap_or_default();
+
+    let id: Uuid = if is_request {
+        sqlx::query_scalar(
+            "INSERT INTO request (
+                id, source, destination,
+                pickup_earliest, pickup_latest, dropoff_earliest, dropoff_latest,
+                weight, volume, pallets
+            )
+            VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING id;",
+        )
         .bind(from.id)
         .bind(to.id)
+        .bind(from_window.earliest)
+        .bind(from_window.latest)
+        .bind(to_window.earliest)
+        .bind(to_window.latest)
+        .bind(cargo.weight)
+        .bind(cargo.volume)
+        .bind(cargo.pallets)
         .fetch_one(&mut *tx)
-        .await?;
-
-    if sqlx::query("SELECT 1 FROM segment WHERE s1 = $1 and s2 = $2")
-        .bind(&from.id)
-        .bind(&to.id)
-        .fetch_optional(&mut *tx)
         .await?
-        .is_none() 
-    {
-        let route = client
-            .create_route(map_service::CreateRouteRequest {
-                stops: vec![
-                    [from.coords.lat, from.coords.lon],
-                    [to.coords.lat, to.coords.lon],
-                ],
-            })
-            .await
-            .map_err(|e| ErrorResponse::new(format!("map service returned error: {e}")))?;
-
-        sqlx::query(
-            "INSERT INTO segment (s1, s2, points, distance, time)
-            VALUES ($1, $2, $3, $4, $5);",
+    } else {
+        sqlx::query_scalar(
+            "INSERT INTO trip (
+                id, source, destination, departure,
+                capacity_weight, capacity_volume, capacity_pallets
+            )
+            VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, $6)
+            RETURNING id;",
         )
         .bind(from.id)
         .bind(to.id)
-        .bind(
-            route
-                .way
-                .into_iter()
-                .map(|[x, y]| PgPoint { x, y })
-                .collect::<Vec<_>>(),
-        )
-        .bind(route.distance as i32)
-        .bind(route.duration as i32)
-        .execute(&mut *tx)
-        .await?;
-    }
+        .bind(r.departure)
+        .bind(capacity.weight)
+        .bind(capacity.volume)
+        .bind(capacity.pallets)
+        .fetch_one(&mut *tx)
+        .await?
+    };
+
+    segments::store(&mut tx, &fetched).await?;
 
     if !is_request {
         sqlx::query(
@@ -107,9 +158,9 @@ async fn create_route(
 
     tx.commit()
         .await
-        .map_err(|e| ErrorResponse::new(format!("error commiting transaction: {e}")))?;
+        .map_err(|e| Error::Database(format!("error commiting transaction: {e}")))?;
 
-    Ok(id)
+    Ok((id, warnings))
 }
 
 pub async fn create_cargo_request(
@@ -117,8 +168,8 @@ pub async fn create_cargo_request(
     State(client): State<map_service::Client>,
     Json(r): Json<CreateRouteRequest>,
 ) -> Result<Json<CreateRouteResponse>> {
-    let id = create_route(&client, &pool, &r.from_station, &r.to_station, true).await?;
-    Ok(Json(CreateRouteResponse { id }))
+    let (id, warnings) = create_route(&client, &pool, &r, true).await?;
+    Ok(Json(CreateRouteResponse { id, warnings }))
 }
 
 pub async fn create_trip(
@@ -126,15 +177,31 @@ pub async fn create_trip(
     State(client): State<map_service::Client>,
     Json(r): Json<CreateRouteRequest>,
 ) -> Result<Json<CreateRouteResponse>> {
-    let id = create_route(&client, &pool, &r.from_station, &r.to_station, false).await?;
-    Ok(Json(CreateRouteResponse { id }))
+    let (id, warnings) = create_route(&client, &pool, &r, false).await?;
+    Ok(Json(CreateRouteResponse { id, warnings }))
+}
+
+/// Fetches the segment a cargo request is missing, if the request exists.
+async fn ensure_request_segment(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    id: &Uuid,
+) -> Result<()> {
+    if let Some((src_id, src_coords, dst_id, dst_coords)) = get_request_stations(pool, id).await? {
+        segments::ensure(pool, client, &[(src_id, src_coords), (dst_id, dst_coords)]).await?;
+    }
+
+    Ok(())
 }
 
 pub async fn get_cargo_request(
     State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
     Path(r): Path<GetWaypointsRequest>,
 ) -> Result<Json<GetWaypointsResponse>> {
-    let info: Option<(Uuid, String, PgPoint, Uuid, String, PgPoint, i32, i32)> = sqlx::query_as(
+    ensure_request_segment(&pool, &client, &r.id).await?;
+
+    let info: Option<SegmentRow> = sqlx::query_as(
         "SELECT 
             s_source.id AS source_id,
             s_source.address AS source_address,
@@ -150,12 +217,12 @@ pub async fn get_cargo_request(
         LEFT JOIN segment seg ON seg.s1 = r.source AND seg.s2 = r.destination
         WHERE r.id = $1;",
     )
-    .bind(&r.id)
+    .bind(r.id)
     .fetch_optional(&pool)
     .await?;
 
     let Some(info) = info else {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "cannot find cargo request with id {}",
             r.id
         )));
@@ -169,25 +236,23 @@ pub async fn get_cargo_request(
                 station: Station {
                     id: src_id,
                     address: src_addr,
-                    coords: Coords {
-                        lat: src_coords.x,
-                        lon: src_coords.y,
-                    },
+                    coords: src_coords,
                 },
                 distance: 0,
                 trip_time: 0,
+                arrival: None,
+                load: None,
             },
             Waypoint {
                 station: Station {
                     id: dst_id,
                     address: dst_addr,
-                    coords: Coords {
-                        lat: dst_coords.x,
-                        lon: dst_coords.y,
-                    },
+                    coords: dst_coords,
                 },
-                distance: distance as u64,
-                trip_time: time as u64,
+                distance: distance.unwrap_or_default() as u64,
+                trip_time: time.unwrap_or_default() as u64,
+                arrival: None,
+                load: None,
             },
         ],
     };
@@ -197,9 +262,12 @@ pub async fn get_cargo_request(
 
 pub async fn get_trip(
     State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
     Path(r): Path<GetWaypointsRequest>,
 ) -> Result<Json<GetWaypointsResponse>> {
-    let segments: Vec<(Uuid, String, PgPoint, Uuid, String, PgPoint, i32, i32)> = sqlx::query_as(
+    segments::ensure(&pool, &client, &paths::get(&pool, &r.id).await?).await?;
+
+    let segments: Vec<SegmentRow> = sqlx::query_as(
         "SELECT 
             s_source.id AS source_id,
             s_source.address AS source_address,
@@ -217,46 +285,74 @@ pub async fn get_trip(
         WHERE p1.trip_id = $1
         ORDER BY p1.index;",
     )
-    .bind(&r.id)
+    .bind(r.id)
     .fetch_all(&pool)
     .await?;
 
     if segments.is_empty() {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "cannot find trip with id {}",
             r.id
         )));
     }
 
+    let path: Vec<Uuid> = std::iter::once(segments[0].0)
+        .chain(segments.iter().map(|segment| segment.3))
+        .collect();
+    let stops = planner::fixed(&path);
+    let constraints = load_constraints(&pool, &r.id, &stops, &[]).await?;
+    let loads = constraints.capacity.profile(&stops);
+    let load = |index: usize| {
+        let load = loads[index];
+        Some(Cargo {
+            weight: load.weight,
+            volume: load.volume,
+            pallets: load.pallets as i32,
+        })
+    };
+    let arrivals = constraints
+        .schedule
+        .arrivals(
+            &stops,
+            segments
+                .iter()
+                .map(|segment| segment.7.unwrap_or_default() as f64),
+        )
+        .map(|arrivals| {
+            arrivals
+                .into_iter()
+                .map(|t| t.round() as i64)
+                .collect::<Vec<_>>()
+        });
+    let arrival = |index: usize| arrivals.as_ref().map(|arrivals| arrivals[index]);
+
     let mut waypoints = Vec::new();
 
     waypoints.push(Waypoint {
         station: Station {
             id: segments[0].0,
             address: segments[0].1.clone(),
-            coords: Coords {
-                lat: segments[0].2.x,
-                lon: segments[0].2.y,
-            },
+            coords: segments[0].2,
         },
 
         distance: 0,
         trip_time: 0,
+        arrival: arrival(0),
+        load: load(0),
     });
 
-    for segment in segments {
+    for (index, segment) in segments.into_iter().enumerate() {
         waypoints.push(Waypoint {
             station: Station {
                 id: segment.3,
                 address: segment.4.clone(),
-                coords: Coords {
-                    lat: segment.5.x,
-                    lon: segment.5.y,
-                },
+                coords: segment.5,
             },
 
-            distance: segment.6 as u64,
-            trip_time: segment.7 as u64,
+            distance: segment.6.unwrap_or_default() as u64,
+            trip_time: segment.7.unwrap_or_default() as u64,
+            arrival: arrival(index + 1),
+            load: load(index + 1),
         });
     }
 
@@ -266,7 +362,7 @@ pub async fn get_trip(
 }
 
 async fn fetch_request_points(pool: &sqlx::PgPool, request_id: &Uuid) -> Result<Vec<[f64; 2]>> {
-    let pg_points: Option<Vec<PgPoint>> = sqlx::query_scalar(
+    let pg_points: Option<Option<Vec<Coord>>> = sqlx::query_scalar(
         "SELECT     
             seg.points
         FROM request r        
@@ -279,26 +375,29 @@ async fn fetch_request_points(pool: &sqlx::PgPool, request_id: &Uuid) -> Result<
     .fetch_optional(pool)
     .await?;
 
-    let Some(pg_points) = pg_points else {
-        return Err(ErrorResponse::new(format!(
+    let Some(pg_points) = pg_points.flatten() else {
+        return Err(Error::NotFound(format!(
             "there are no points for request id {}",
             request_id
         )));
     };
 
-    let points = pg_points.into_iter().map(|p| [p.x, p.y]).collect();
+    let points = pg_points.into_iter().map(Coord::to_lat_lon).collect();
 
     Ok(points)
 }
 
 pub async fn get_cargo_request_points(
     State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
     Path(r): Path<GetPointsRequest>,
 ) -> Result<Json<GetPointsResponse>> {
+    ensure_request_segment(&pool, &client, &r.id).await?;
+
     let points = fetch_request_points(&pool, &r.id).await?;
 
     if points.is_empty() {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "cannot find cargo request points for id {}",
             r.id
         )));
@@ -308,7 +407,7 @@ pub async fn get_cargo_request_points(
 }
 
 async fn fetch_trip_points(pool: &sqlx::PgPool, trip_id: &Uuid) -> Result<Vec<[f64; 2]>> {
-    let pg_points: Option<Vec<PgPoint>> = sqlx::query_scalar(
+    let pg_points: Option<Vec<Coord>> = sqlx::query_scalar(
         "SELECT array_agg(point ORDER BY p1.index, idx) AS flat_points
         FROM path p1
         JOIN path p2 ON p1.trip_id = p2.trip_id AND p2.index = p1.index + 1
@@ -321,41 +420,28 @@ async fn fetch_trip_points(pool: &sqlx::PgPool, trip_id: &Uuid) -> Result<Vec<[f
     .await?;
 
     let Some(pg_points) = pg_points else {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "there are no points for trip id {}",
             trip_id
         )));
     };
 
-    let points = pg_points.into_iter().map(|p| [p.x, p.y]).collect();
+    let points = pg_points.into_iter().map(Coord::to_lat_lon).collect();
 
     Ok(points)
 }
 
-fn distance(p1: &PgPoint, p2: &PgPoint) -> f64 {
-    const R: f64 = 6371000.0;
-
-    let lat1 = p1.y.to_radians();
-    let lon1 = p1.x.to_radians();
-    let lat2 = p2.y.to_radians();
-    let lon2 = p2.x.to_radians();
-
-    let dlat = lat2 - lat1;
-    let dlon = lon2 - lon1;
-
-    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
-
-    R * 2.0 * a.sqrt().atan2((1.0 - a).sqrt())
-}
-
 pub async fn get_trip_points(
     State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
     Path(r): Path<GetPointsRequest>,
 ) -> Result<Json<GetPointsResponse>> {
+    segments::ensure(&pool, &client, &paths::get(&pool, &r.id).await?).await?;
+
     let points = fetch_trip_points(&pool, &r.id).await?;
 
     if points.is_empty() {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "cannot find trip points for id {}",
             r.id
         )));
@@ -364,114 +450,630 @@ pub async fn get_trip_points(
     Ok(Json(GetPointsResponse { points }))
 }
 
-pub async fn get_potential_routes(
+/// Returns every version of the trip `id` belongs to, oldest first.
+async fn fetch_trip_history(pool: &sqlx::PgPool, id: &Uuid) -> Result<Vec<TripVersion>> {
+    let versions: Vec<TripVersionRow> = sqlx::query_as(
+        "WITH RECURSIVE ancestors AS (
+            SELECT id, parent_trip_id FROM trip WHERE id = $1
+            UNION ALL
+            SELECT t.id, t.parent_trip_id
+            FROM trip t
+            INNER JOIN ancestors a ON t.id = a.parent_trip_id
+        ), versions AS (
+            SELECT t.id, t.parent_trip_id, t.version, t.status
+            FROM trip t
+            INNER JOIN ancestors a ON t.id = a.id AND a.parent_trip_id IS NULL
+            UNION ALL
+            SELECT t.id, t.parent_trip_id, t.version, t.status
+            FROM trip t
+            INNER JOIN versions v ON t.parent_trip_id = v.id
+        )
+        SELECT v.id, v.parent_trip_id, v.version, v.status,
+            COALESCE(array_agg(m.request_id ORDER BY m.request_id)
+                FILTER (WHERE m.request_id IS NOT NULL), '{}')
+        FROM versions v
+        LEFT JOIN trip_merge m ON m.trip_id = v.id
+        GROUP BY v.id, v.parent_trip_id, v.version, v.status
+        ORDER BY v.version;",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?;
+
+    if versions.is_empty() {
+        return Err(Error::NotFound(format!("cannot find trip with id {}", id)));
+    }
+
+    versions
+        .into_iter()
+        .map(|(route, parent, version, status, merged_requests)| {
+            Ok(TripVersion {
+                route,
+                parent,
+                version,
+                status: status.parse().map_err(Error::Database)?,
+                merged_requests,
+            })
+        })
+        .collect()
+}
+
+pub async fn get_latest_trip(
     State(pool): State<sqlx::PgPool>,
-    Json(r): Json<GetPotentialRoutesRequest>,
-) -> Result<Json<GetPotentialRoutesResponse>> {
-    let mut trip_stations: Vec<PgPoint> = sqlx::query_scalar(
-        "SELECT s.coords
-        FROM path p
-        INNER JOIN station s ON p.station_id = s.id
-        WHERE p.trip_id = $1
-        ORDER BY p.index;",
+    Path(r): Path<GetTripVersionRequest>,
+) -> Result<Json<TripVersion>> {
+    let mut versions = fetch_trip_history(&pool, &r.id).await?;
+
+    Ok(Json(
+        versions.pop().expect("history has at least one version"),
+    ))
+}
+
+/// Moves a trip or cargo request, a row of `table`, to the `next` status.
+async fn change_status(
+    pool: &sqlx::PgPool,
+    table: &str,
+    kind: &str,
+    id: &Uuid,
+    next: Status,
+) -> Result<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    let current: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT status FROM {table} WHERE id = $1 FOR UPDATE;"
+    ))
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(current) = current else {
+        return Err(Error::NotFound(format!("cannot find {kind} with id {id}")));
+    };
+
+    let current: Status = current.parse().map_err(Error::Database)?;
+
+    // Only the latest version of a trip is the trip as it is now.
+    if table == "trip" {
+        check_latest(&mut *tx, id).await?;
+    }
+
+    if !current.can_become(next) {
+        return Err(Error::Conflict(format!(
+            "{kind} {id} cannot change from {} to {}",
+            current.as_str(),
+            next.as_str()
+        )));
+    }
+
+    sqlx::query(&format!("UPDATE {table} SET status = $2 WHERE id = $1;"))
+        .bind(id)
+        .bind(next.as_str())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| Error::Database(format!("error committing transaction: {e}")))?;
+
+    Ok(())
+}
+
+pub async fn change_trip_status(
+    State(pool): State<sqlx::PgPool>,
+    Path(path): Path<ChangeStatusPath>,
+    Json(r): Json<ChangeStatusRequest>,
+) -> Result<Json<ChangeStatusResponse>> {
+    change_status(&pool, "trip", "trip", &path.id, r.status).await?;
+
+    Ok(Json(ChangeStatusResponse {
+        route: path.id,
+        status: r.status,
+    }))
+}
+
+pub async fn change_cargo_request_status(
+    State(pool): State<sqlx::PgPool>,
+    Path(path): Path<ChangeStatusPath>,
+    Json(r): Json<ChangeStatusRequest>,
+) -> Result<Json<ChangeStatusResponse>> {
+    change_status(&pool, "request", "cargo request", &path.id, r.status).await?;
+
+    Ok(Json(ChangeStatusResponse {
+        route: path.id,
+        status: r.status,
+    }))
+}
+
+pub async fn get_trip_history(
+    State(pool): State<sqlx::PgPool>,
+    Path(r): Path<GetTripVersionRequest>,
+) -> Result<Json<GetTripHistoryResponse>> {
+    let versions = fetch_trip_history(&pool, &r.id).await?;
+
+    Ok(Json(GetTripHistoryResponse { versions }))
+}
+
+/// Fails unless `trip` exists and is neither completed nor cancelled.
+async fn check_trip_open<'c, E>(executor: E, trip: &Uuid) -> Result<()>
+where
+    E: sqlx::PgExecutor<'c>,
+{
+    let status: Option<String> = sqlx::query_scalar("SELECT status FROM trip WHERE id = $1;")
+        .bind(trip)
+        .fetch_optional(executor)
+        .await?;
+
+    let Some(status) = status else {
+        return Err(Error::NotFound(format!(
+            "cannot find trip with id {}",
+            trip
+        )));
+    };
+
+    let status: Status = status.parse().map_err(Error::Database)?;
+
+    if status.is_closed() {
+        return Err(Error::Conflict(format!(
+            "trip {} is {} and cannot be planned",
+            trip,
+            status.as_str()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fails if one of `requests` is under way or closed, as such a request can
+/// no longer leave its trip.
+async fn check_detachable<'c, E>(executor: E, requests: &[Uuid]) -> Result<()>
+where
+    E: sqlx::PgExecutor<'c>,
+{
+    let stuck: Option<(Uuid, String)> = sqlx::query_as(
+        "SELECT id, status FROM request
+        WHERE id = ANY($1) AND status NOT IN ('draft', 'planned')
+        ORDER BY id
+        LIMIT 1;",
     )
-    .bind(&r.trip)
-    .fetch_all(&pool)
+    .bind(requests)
+    .fetch_optional(executor)
+    .await?;
+
+    if let Some((id, status)) = stuck {
+        return Err(Error::Conflict(format!(
+            "cargo request {} is {} and cannot be detached from its trip",
+            id, status
+        )));
+    }
+
+    Ok(())
+}
+
+/// Leaves out the completed and cancelled ones of `requests`, keeping the order.
+async fn open_requests(pool: &sqlx::PgPool, requests: &[Uuid]) -> Result<Vec<Uuid>> {
+    let statuses: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, status FROM request WHERE id = ANY($1);")
+            .bind(requests)
+            .fetch_all(pool)
+            .await?;
+
+    let mut closed = Vec::new();
+    for (id, status) in statuses {
+        if status
+            .parse::<Status>()
+            .map_err(Error::Database)?
+            .is_closed()
+        {
+            closed.push(id);
+        }
+    }
+
+    Ok(requests
+        .iter()
+        .filter(|id| !closed.contains(id))
+        .copied()
+        .collect())
+}
+
+/// Window between the given bounds, `None` if neither is set.
+fn window(earliest: Option<i64>, latest: Option<i64>) -> Option<planner::Window> {
+    if earliest.is_none() && latest.is_none() {
+        return None;
+    }
+
+    Some(planner::Window {
+        earliest: earliest.map(|t| t as f64),
+        latest: latest.map(|t| t as f64),
+    })
+}
+
+/// Loads the departure and capacity of `trip` and the time windows and cargo
+/// of the cargo requests on it, which apply to the stops of its `path` they
+/// ride between, and of the `requests` to be planned into it, which apply to
+/// their pickups and drop-offs.
+async fn load_constraints(
+    pool: &sqlx::PgPool,
+    trip: &Uuid,
+    path: &[planner::Stop],
+    requests: &[Uuid],
+) -> Result<planner::Constraints> {
+    let trip_row: Option<TripRow> = sqlx::query_as(
+        "SELECT departure, capacity_weight, capacity_volume, capacity_pallets
+        FROM trip
+        WHERE id = $1;",
+    )
+    .bind(trip)
+    .fetch_optional(pool)
+    .await?;
+
+    let (departure, weight, volume, pallets) = trip_row.unwrap_or_default();
+
+    let rows: Vec<RequestRow> = sqlx::query_as(
+        "SELECT id, source, destination,
+            pickup_earliest, pickup_latest, dropoff_earliest, dropoff_latest,
+            weight, volume, pallets
+        FROM request
+        WHERE trip_id = $1 OR id = ANY($2);",
+    )
+    .bind(trip)
+    .bind(requests)
+    .fetch_all(pool)
     .await?;
 
+    let mut schedule = planner::Schedule::new(departure.map(|t| t as f64));
+    let mut capacity = planner::Capacity::new(weight, volume, pallets.map(|p| p as f64));
+
+    for row in rows {
+        let (id, source, destination) = (row.0, row.1, row.2);
+
+        let (pickup, dropoff) = if requests.contains(&id) {
+            (
+                planner::Stop::Pickup {
+                    request: id,
+                    station: source,
+                },
+                planner::Stop::Dropoff {
+                    request: id,
+                    station: destination,
+                },
+            )
+        } else if let Some(visits) = planner::visits(path, source, destination) {
+            visits
+        } else {
+            continue;
+        };
+
+        if let Some(window) = window(row.3, row.4) {
+            schedule.add_window(pickup, window);
+        }
+        if let Some(window) = window(row.5, row.6) {
+            schedule.add_window(dropoff, window);
+        }
+        capacity.add_cargo(
+            pickup,
+            dropoff,
+            planner::Load {
+                weight: row.7,
+                volume: row.8,
+                pallets: row.9 as f64,
+            },
+        );
+    }
+
+    Ok(planner::Constraints { schedule, capacity })
+}
+
+/// Trip, cargo requests and travel costs between their stations, as needed
+/// to plan the requests into the trip.
+struct Planning {
+    trip: Vec<planner::Stop>,
+    requests: Vec<planner::Request>,
+    costs: planner::Costs,
+    constraints: planner::Constraints,
+}
+
+/// When costs between the stations of different cargo requests are fetched,
+/// as needed to plan several requests together.
+#[derive(Clone, Copy)]
+enum BetweenRequests {
+    Never,
+    /// Only when a stop has a time window, as ordering the requests by their
+    /// windows needs the road times between them.
+    Timed,
+    Always,
+}
+
+/// Loads what is needed to plan `request_ids` into `trip_id`, with costs
+/// between stations of different requests as `between` says. Time windows are
+/// checked against road times, so road costs are loaded and missing segments
+/// fetched whenever a stop has one, whatever the `options` ask for, and a
+/// segment that cannot be fetched then fails the call.
+async fn load_planning(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    trip_id: &Uuid,
+    request_ids: &[Uuid],
+    options: &ScoringOptions,
+    between: BetweenRequests,
+) -> Result<Planning> {
+    let trip_stations = paths::get(pool, trip_id).await?;
+
     if trip_stations.is_empty() {
-        return Err(ErrorResponse::new(format!(
+        return Err(Error::NotFound(format!(
             "cannot find trip with id {}",
-            r.trip
+            trip_id
         )));
     }
 
-    let mut route_ids = Vec::new();
+    check_trip_open(pool, trip_id).await?;
+    let request_ids = open_requests(pool, request_ids).await?;
 
-    for id in &r.cargo_requests {
-        let request: Option<(PgPoint, PgPoint)> = sqlx::query_as(
-            "SELECT 
-                s_source.coords AS source_coords,
-                s_dest.coords AS destination_coords
-            FROM request r
-            INNER JOIN station s_source ON r.source = s_source.id
-            INNER JOIN station s_dest ON r.destination = s_dest.id
-            WHERE r.id = $1;",
-        )
-        .bind(id)
-        .fetch_optional(&pool)
-        .await?;
+    let mut costs = planner::Costs::default();
+    for (id, coords) in &trip_stations {
+        costs.add_station(*id, *coords);
+    }
+
+    let mut requests = Vec::new();
 
-        let Some(request) = request else {
-            return Err(ErrorResponse::new(format!(
+    for id in &request_ids {
+        let Some((src_id, src_coords, dst_id, dst_coords)) = get_request_stations(pool, id).await?
+        else {
+            return Err(Error::NotFound(format!(
                 "cannot find cargo request with id {}",
                 id
             )));
         };
 
-        let (insert_src_idx, _) = trip_stations
-            .windows(2)
-            .map(|stations| {
-                distance(&stations[0], &request.0) + distance(&request.0, &stations[1])
-                    - distance(&stations[0], &stations[1])
-            })
-            .enumerate()
-            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
-            .unwrap();
+        costs.add_station(src_id, src_coords);
+        costs.add_station(dst_id, dst_coords);
+        requests.push(planner::Request {
+            id: *id,
+            pickup: src_id,
+            dropoff: dst_id,
+        });
+    }
 
-        trip_stations.insert(insert_src_idx + 1, request.0);
+    let trip: Vec<Uuid> = trip_stations.iter().map(|(id, _)| *id).collect();
+    let trip_stops = planner::fixed(&trip);
+    let request_ids: Vec<Uuid> = requests.iter().map(|r| r.id).collect();
+    let constraints = load_constraints(pool, trip_id, &trip_stops, &request_ids).await?;
+    let timed = constraints.schedule.has_windows();
+    let between_requests = match between {
+        BetweenRequests::Never => false,
+        BetweenRequests::Timed => timed,
+        BetweenRequests::Always => true,
+    };
 
-        let (insert_dst_idx, _) = trip_stations
-            .windows(2)
-            .map(|stations| {
-                distance(&stations[0], &request.1) + distance(&request.1, &stations[1])
-                    - distance(&stations[0], &stations[1])
-            })
-            .enumerate()
-            .skip(insert_src_idx + 1)
-            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
-            .unwrap();
-
-        trip_stations.insert(insert_dst_idx + 1, request.1);
-
-        let res_distance = distance(
-            &trip_stations[insert_src_idx],
-            &trip_stations[insert_src_idx + 1],
-        ) + distance(
-            &trip_stations[insert_src_idx + 1],
-            &trip_stations[insert_src_idx + 2],
-        ) - distance(
-            &trip_stations[insert_src_idx],
-            &trip_stations[insert_src_idx + 2],
-        ) + distance(
-            &trip_stations[insert_dst_idx],
-            &trip_stations[insert_dst_idx + 1],
-        ) + distance(
-            &trip_stations[insert_dst_idx + 1],
-            &trip_stations[insert_dst_idx + 2],
-        ) - distance(
-            &trip_stations[insert_dst_idx],
-            &trip_stations[insert_dst_idx + 2],
-        );
+    if options.metric == ScoringMetric::Road || timed {
+        let request_stations: Vec<Uuid> = requests
+            .iter()
+            .flat_map(|r| [r.pickup, r.dropoff])
+            .collect();
+        let stations: Vec<Uuid> = trip.iter().chain(&request_stations).copied().collect();
 
-        trip_stations.remove(insert_dst_idx + 1);
-        trip_stations.remove(insert_src_idx + 1);
+        for (s1, s2, distance, time) in segments::between(pool, &stations).await? {
+            costs.add_segment(s1, s2, distance as f64, time as f64);
+        }
 
-        route_ids.push((id, res_distance));
+        if options.fetch_missing || timed {
+            let mut pairs = Vec::new();
+
+            for request in &requests {
+                for station in &trip {
+                    pairs.extend([
+                        (*station, request.pickup),
+                        (request.pickup, *station),
+                        (*station, request.dropoff),
+                        (request.dropoff, *station),
+                    ]);
+                }
+                pairs.push((request.pickup, request.dropoff));
+            }
+
+            if between_requests {
+                for s1 in &request_stations {
+                    pairs.extend(request_stations.iter().map(|s2| (*s1, *s2)));
+                }
+            }
+
+            pairs.sort();
+            pairs.dedup();
+            pairs.retain(|(s1, s2)| !costs.has_segment(*s1, *s2));
+
+            let pairs = pairs
+                .into_iter()
+                .map(|(s1, s2)| ((s1, costs.coords(s1)), (s2, costs.coords(s2))))
+                .collect();
+
+            // Windows are only checked right if every road time is known.
+            for segment in segments::cache(pool, client, pairs, timed).await? {
+                costs.add_segment(
+                    segment.s1,
+                    segment.s2,
+                    segment.distance as f64,
+                    segment.time as f64,
+                );
+            }
+        }
     }
 
-    route_ids.sort_by(|a, b| a.1.total_cmp(&b.1));
-    route_ids.retain(|(_, distance)| *distance < 10000.0);
+    Ok(Planning {
+        trip: trip_stops,
+        requests,
+        costs,
+        constraints,
+    })
+}
+
+fn limits(defaults: &planner::Limits, options: &ScoringOptions) -> planner::Limits {
+    planner::Limits {
+        max_added_distance: options.max_added_distance.or(defaults.max_added_distance),
+        max_added_time: options.max_added_time.or(defaults.max_added_time),
+        max_detour_percent: options.max_detour_percent.or(defaults.max_detour_percent),
+        objective: options.objective.unwrap_or(defaults.objective),
+    }
+}
+
+/// Arrival times along `stops` in whole seconds, empty if the trip has no departure time.
+fn arrival_times(
+    schedule: &planner::Schedule,
+    costs: &planner::Costs,
+    stops: &[planner::Stop],
+) -> Vec<i64> {
+    schedule
+        .arrivals_by(costs, stops)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| t.round() as i64)
+        .collect()
+}
+
+pub async fn get_potential_routes(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    State(defaults): State<planner::Limits>,
+    Json(r): Json<GetPotentialRoutesRequest>,
+) -> Result<Json<GetPotentialRoutesResponse>> {
+    r.options.check()?;
+    check_latest(&pool, &r.trip).await?;
+
+    let planning = load_planning(
+        &pool,
+        &client,
+        &r.trip,
+        &r.cargo_requests,
+        &r.options,
+        BetweenRequests::Never,
+    )
+    .await?;
+
+    let limits = limits(&defaults, &r.options);
+    let base = planning.costs.route(&planner::stations(&planning.trip));
+
+    // Requests that cannot meet the constraints are still shown, placed as
+    // if there were none.
+    let unconstrained = planner::Constraints::default();
+    let mut candidates = planning
+        .requests
+        .iter()
+        .map(|request| {
+            let insert = |constraints| {
+                planner::insert(
+                    &planning.costs,
+                    constraints,
+                    limits.objective,
+                    &planning.trip,
+                    request,
+                )
+            };
+
+            match insert(&planning.constraints) {
+                Some(insertion) => Ok((insertion, true, true)),
+                None => {
+                    let Some(insertion) = insert(&unconstrained) else {
+                        return Err(Error::Validation(format!(
+                            "trip {} has fewer than two stops, cargo requests cannot be placed on it",
+                            r.trip
+                        )));
+                    };
+                    let constraints = &planning.constraints;
+                    let within_time_windows = constraints
+                        .schedule
+                        .feasible(&planning.costs, &insertion.stops);
+                    let within_capacity = constraints.capacity.feasible(&insertion.stops);
+
+                    Ok((insertion, within_time_windows, within_capacity))
+                }
+            }
+        })
+        .collect::<Result<Vec<(planner::Insertion, bool, bool)>>>()?;
+
+    candidates.sort_by(|(a, ..), (b, ..)| {
+        a.added(limits.objective)
+            .total_cmp(&b.added(limits.objective))
+    });
+
+    let candidates: Vec<PotentialRoute> = candidates
+        .into_iter()
+        .map(
+            |(insertion, within_time_windows, within_capacity)| PotentialRoute {
+                route: insertion.request,
+                metric: insertion.metric,
+                added_distance: insertion.added_distance.max(0.0) as u64,
+                added_time: insertion.added_time.max(0.0) as u64,
+                pickup_index: insertion.pickup,
+                dropoff_index: insertion.dropoff,
+                within_limits: limits.allows(&base, insertion.added_distance, insertion.added_time),
+                within_time_windows,
+                within_capacity,
+                arrivals: arrival_times(
+                    &planning.constraints.schedule,
+                    &planning.costs,
+                    &insertion.stops,
+                ),
+                stations: planner::stations(&insertion.stops),
+            },
+        )
+        .collect();
 
     Ok(Json(GetPotentialRoutesResponse {
-        requests: route_ids.into_iter().map(|(id, _)| id.clone()).collect(),
+        requests: candidates
+            .iter()
+            .filter(|candidate| {
+                candidate.within_limits
+                    && candidate.within_time_windows
+                    && candidate.within_capacity
+            })
+            .map(|candidate| candidate.route)
+            .collect(),
+        candidates,
+    }))
+}
+
+pub async fn get_combined_routes(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    State(defaults): State<planner::Limits>,
+    Json(r): Json<GetCombinedRoutesRequest>,
+) -> Result<Json<GetCombinedRoutesResponse>> {
+    r.options.check()?;
+    check_latest(&pool, &r.trip).await?;
+
+    let planning = load_planning(
+        &pool,
+        &client,
+        &r.trip,
+        &r.cargo_requests,
+        &r.options,
+        BetweenRequests::Always,
+    )
+    .await?;
+
+    let limits = limits(&defaults, &r.options);
+    let selection = planner::select(
+        &planning.costs,
+        &planning.constraints,
+        &limits,
+        &planning.trip,
+        &planning.requests,
+    );
+
+    Ok(Json(GetCombinedRoutesResponse {
+        requests: selection.requests,
+        stations: planner::stations(&selection.stops),
+        added_distance: selection.added_distance.max(0.0) as u64,
+        added_time: selection.added_time.max(0.0) as u64,
+        metric: selection.metric,
     }))
 }
 
 async fn get_request_stations(
     pool: &sqlx::PgPool,
     id: &Uuid,
-) -> Result<Option<(uuid::Uuid, PgPoint, uuid::Uuid, PgPoint)>> {
+) -> Result<Option<(uuid::Uuid, Coord, uuid::Uuid, Coord)>> {
     let stations = sqlx::query_as(
         "SELECT 
             s_src.id AS src_station_id,
@@ -490,155 +1092,754 @@ async fn get_request_stations(
     Ok(stations)
 }
 
+/// Station order of a trip with cargo requests merged in.
+struct MergePlan {
+    /// Stations of the trip the plan starts from.
+    path: Vec<(Uuid, Coord)>,
+    stations: Vec<(Uuid, Coord)>,
+    stops: Vec<planner::Stop>,
+    constraints: planner::Constraints,
+}
+
+/// Plans `requests` into the trip, by road if any stop has a time window.
+/// Fails if they cannot all be served within their time windows and the
+/// trip's capacity.
+async fn plan_merge(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    objective: planner::Objective,
+    trip: &Uuid,
+    requests: &[Uuid],
+) -> Result<MergePlan> {
+    let planning = load_planning(
+        pool,
+        client,
+        trip,
+        requests,
+        &ScoringOptions::default(),
+        BetweenRequests::Timed,
+    )
+    .await?;
+
+    let Some(stops) = planner::optimise(
+        &planning.costs,
+        &planning.constraints,
+        objective,
+        &planning.trip,
+        &planning.requests,
+    ) else {
+        return Err(Error::Validation(format!(
+            "cargo requests cannot be merged into trip {} within their time windows and its capacity",
+            trip
+        )));
+    };
+
+    let station = |stop: &planner::Stop| (stop.station(), planning.costs.coords(stop.station()));
+
+    // A pickup or drop-off at the station next to it is the same visit.
+    let mut stations: Vec<(Uuid, Coord)> = stops.iter().map(station).collect();
+    stations.dedup_by(|a, b| a.0 == b.0);
+
+    Ok(MergePlan {
+        path: planning.trip.iter().map(station).collect(),
+        stations,
+        stops,
+        constraints: planning.constraints,
+    })
+}
+
+/// Returns the trip every one of `requests` is currently assigned to, if any.
+async fn get_assignments(
+    tx: &mut sqlx::PgConnection,
+    requests: &[Uuid],
+    lock: bool,
+) -> Result<Vec<(Uuid, Option<Uuid>)>> {
+    let query = if lock {
+        "SELECT id, trip_id FROM request WHERE id = ANY($1) ORDER BY id FOR UPDATE;"
+    } else {
+        "SELECT id, trip_id FROM request WHERE id = ANY($1) ORDER BY id;"
+    };
+
+    let assignments = sqlx::query_as(query)
+        .bind(requests)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    Ok(assignments)
+}
+
+/// Checks that none of `requests` is assigned to a trip yet, unless `reassign`
+/// is set. Returns the trips the requests would be taken from.
+fn check_assignments(
+    trip: &Uuid,
+    assignments: &[(Uuid, Option<Uuid>)],
+    reassign: bool,
+) -> Result<Vec<Uuid>> {
+    let mut old_trips = Vec::new();
+
+    for (request, assigned) in assignments {
+        let Some(assigned) = assigned else {
+            continue;
+        };
+
+        if assigned == trip {
+            return Err(Error::Conflict(format!(
+                "cargo request {} is already assigned to trip {}",
+                request, trip
+            )));
+        }
+
+        if !reassign {
+            return Err(Error::Conflict(format!(
+                "cargo request {} is already assigned to trip {}, set reassign to move it",
+                request, assigned
+            )));
+        }
+
+        if !old_trips.contains(assigned) {
+            old_trips.push(*assigned);
+        }
+    }
+
+    Ok(old_trips)
+}
+
+/// Fails with a conflict if `trip` was already merged into a newer version,
+/// so that planning and merges always continue from the latest one.
+async fn check_latest<'c, E>(executor: E, trip: &Uuid) -> Result<()>
+where
+    E: sqlx::PgExecutor<'c>,
+{
+    let child: Option<Uuid> = sqlx::query_scalar("SELECT id FROM trip WHERE parent_trip_id = $1;")
+        .bind(trip)
+        .fetch_optional(executor)
+        .await?;
+
+    if let Some(child) = child {
+        return Err(Error::Conflict(format!(
+            "trip {} was superseded by version {}, use its latest version",
+            trip, child
+        )));
+    }
+
+    Ok(())
+}
+
+/// Creates the next version of the locked `trip` with the given stations and
+/// moves the cargo requests assigned to it onto the new version. Versions are
+/// never changed once created, so each of them keeps the stations it had.
+async fn create_version(
+    tx: &mut sqlx::PgConnection,
+    trip: &Uuid,
+    stations: &[(Uuid, Coord)],
+) -> Result<Uuid> {
+    let new_trip_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO trip (
+            id, source, destination, parent_trip_id, version, status, departure,
+            capacity_weight, capacity_volume, capacity_pallets
+        )
+        SELECT gen_random_uuid(), $1, $2, id, version + 1, status, departure,
+            capacity_weight, capacity_volume, capacity_pallets
+        FROM trip
+        WHERE id = $3
+        RETURNING id;",
+    )
+    .bind(stations[0].0)
+    .bind(stations[stations.len() - 1].0)
+    .bind(trip)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    paths::insert(tx, &new_trip_id, stations).await?;
+
+    // Cargo requests merged into earlier versions ride on the new one too.
+    sqlx::query("UPDATE request SET trip_id = $1 WHERE trip_id = $2;")
+        .bind(new_trip_id)
+        .bind(trip)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(new_trip_id)
+}
+
+/// Fails if a cargo request is listed more than once.
+fn check_unique(requests: &[Uuid]) -> Result<()> {
+    if let Some(id) = requests
+        .iter()
+        .enumerate()
+        .find_map(|(i, id)| requests[..i].contains(id).then_some(id))
+    {
+        return Err(Error::Validation(format!(
+            "cargo request {} is listed more than once",
+            id
+        )));
+    }
+
+    Ok(())
+}
+
 pub async fn merge_routes(
     State(pool): State<sqlx::PgPool>,
     State(client): State<map_service::Client>,
+    State(defaults): State<planner::Limits>,
     Json(r): Json<MergeRoutesRequest>,
 ) -> Result<Json<MergeRoutesResponse>> {
+    check_unique(&r.requests)?;
+
+    check_trip_open(&pool, &r.trip).await?;
+    let requests = open_requests(&pool, &r.requests).await?;
+
+    if requests.is_empty() {
+        return Err(Error::Validation(
+            "all cargo requests are completed or cancelled, nothing to merge".to_string(),
+        ));
+    }
+
+    let mut conn = pool.acquire().await?;
+    let assignments = get_assignments(&mut conn, &requests, false).await?;
+    drop(conn);
+
+    let old_trips = check_assignments(&r.trip, &assignments, r.reassign)?;
+    check_latest(&pool, &r.trip).await?;
+
+    let plan = plan_merge(&pool, &client, defaults.objective, &r.trip, &requests).await?;
+    let trip_stations = plan.stations;
+
+    let mut fetched = segments::acquire(&pool, &client, &trip_stations).await?;
+
+    // Trips the requests are taken from lose the stations nothing else on them uses.
+    let mut reduced = Vec::new();
+    for trip in old_trips {
+        let old_stations = paths::get(&pool, &trip).await?;
+        let stations = paths::without_requests(&pool, &trip, &old_stations, &requests).await?;
+
+        fetched.extend(segments::acquire(&pool, &client, &stations).await?);
+        reduced.push((trip, old_stations, stations));
+    }
+
     let mut tx = pool
         .begin()
         .await
-        .map_err(|e| ErrorResponse::new(format!("error starting transaction: {e}")))?;
-
-    let mut trip_stations: Vec<(uuid::Uuid, PgPoint)> = sqlx::query_as(
-        "SELECT s.id, s.coords
-        FROM path p
-        INNER JOIN station s ON p.station_id = s.id
-        WHERE p.trip_id = $1
-        ORDER BY p.index;",
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    if get_assignments(&mut tx, &requests, true).await? != assignments {
+        return Err(Error::Conflict(
+            "cargo requests were reassigned concurrently, retry the request".to_string(),
+        ));
+    }
+
+    paths::lock(&mut tx, &r.trip, &plan.path).await?;
+    check_trip_open(&mut *tx, &r.trip).await?;
+    check_latest(&mut *tx, &r.trip).await?;
+
+    for (trip, old_stations, stations) in &reduced {
+        paths::lock(&mut tx, trip, old_stations).await?;
+        create_version(&mut tx, trip, stations).await?;
+    }
+
+    let new_trip_id = create_version(&mut tx, &r.trip, &trip_stations).await?;
+
+    sqlx::query(
+        "INSERT INTO trip_merge (trip_id, request_id)
+        SELECT $1, unnest($2::uuid[]);",
     )
-    .bind(&r.trip)
-    .fetch_all(&mut *tx)
+    .bind(new_trip_id)
+    .bind(&requests)
+    .execute(&mut *tx)
     .await?;
 
-    if trip_stations.is_empty() {
-        return Err(ErrorResponse::new(format!(
-            "cannot find trip with id {}",
-            r.trip
+    segments::store(&mut tx, &fetched).await?;
+
+    sqlx::query(
+        "UPDATE request
+        SET trip_id = $1, status = CASE WHEN status = 'draft' THEN 'planned' ELSE status END
+        WHERE id = ANY($2);",
+    )
+    .bind(new_trip_id)
+    .bind(&requests)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| Error::Database(format!("error committing transaction: {e}")))?;
+
+    Ok(Json(MergeRoutesResponse { route: new_trip_id }))
+}
+
+pub async fn unmerge_routes(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    Json(r): Json<UnmergeRoutesRequest>,
+) -> Result<Json<UnmergeRoutesResponse>> {
+    r.check()?;
+
+    let mut conn = pool.acquire().await?;
+    let assignments = get_assignments(&mut conn, &r.requests, false).await?;
+    drop(conn);
+
+    if let Some(id) = r
+        .requests
+        .iter()
+        .find(|id| !assignments.iter().any(|(request, _)| request == *id))
+    {
+        return Err(Error::NotFound(format!(
+            "cannot find cargo request with id {}",
+            id
         )));
     }
 
-    for request in &r.requests {
-        let Some((req_src_id, req_src_coords, req_dst_id, req_dst_coords)) =
-            get_request_stations(&pool, request).await?
-        else {
-            return Err(ErrorResponse::new(format!(
-                "cannot find cargo request with id {}",
-                request
-            )));
-        };
+    if let Some((id, _)) = assignments
+        .iter()
+        .find(|(_, assigned)| *assigned != Some(r.trip))
+    {
+        return Err(Error::Validation(format!(
+            "cargo request {} is not assigned to trip {}",
+            id, r.trip
+        )));
+    }
 
-        let (insert_src_idx, _) = trip_stations
-            .windows(2)
-            .map(|stations| {
-                distance(&stations[0].1, &req_src_coords)
-                    + distance(&req_src_coords, &stations[1].1)
-                    - distance(&stations[0].1, &stations[1].1)
-            })
-            .enumerate()
-            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
-            .unwrap();
-
-        trip_stations.insert(insert_src_idx + 1, (req_src_id, req_src_coords));
-
-        let (insert_dst_idx, _) = trip_stations
-            .windows(2)
-            .map(|stations| {
-                distance(&stations[0].1, &req_dst_coords)
-                    + distance(&req_dst_coords, &stations[1].1)
-                    - distance(&stations[0].1, &stations[1].1)
-            })
-            .enumerate()
-            .skip(insert_src_idx + 1)
-            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
-            .unwrap();
+    check_detachable(&pool, &r.requests).await?;
+
+    let old_stations = paths::get(&pool, &r.trip).await?;
+    let trip_stations = paths::without_requests(&pool, &r.trip, &old_stations, &r.requests).await?;
+
+    let fetched = segments::acquire(&pool, &client, &trip_stations).await?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
 
-        trip_stations.insert(insert_dst_idx + 1, (req_dst_id, req_dst_coords));
+    paths::lock(&mut tx, &r.trip, &old_stations).await?;
+    check_trip_open(&mut *tx, &r.trip).await?;
+    check_latest(&mut *tx, &r.trip).await?;
+
+    if get_assignments(&mut tx, &r.requests, true).await? != assignments {
+        return Err(Error::Conflict(
+            "cargo requests were reassigned concurrently, retry the request".to_string(),
+        ));
     }
 
-    let new_trip_id: Uuid = sqlx::query_scalar(
-        "INSERT INTO trip (id, source, destination)
-        VALUES (gen_random_uuid(), $1, $2)
-        RETURNING id;",
+    check_detachable(&mut *tx, &r.requests).await?;
+
+    sqlx::query(
+        "UPDATE request
+        SET trip_id = NULL, status = CASE WHEN status = 'planned' THEN 'draft' ELSE status END
+        WHERE id = ANY($1);",
     )
-    .bind(trip_stations[0].0)
-    .bind(trip_stations[trip_stations.len() - 1].0)
-    .fetch_one(&mut *tx)
+    .bind(&r.requests)
+    .execute(&mut *tx)
     .await?;
 
-    // 7. Insert path entries
-    for (index, station) in trip_stations.iter().enumerate() {
-        sqlx::query(
-            "INSERT INTO path (trip_id, station_id, index)
-            VALUES ($1, $2, $3);",
-        )
-        .bind(&new_trip_id)
-        .bind(station.0)
-        .bind(index as i32)
-        .execute(&mut *tx)
-        .await?;
+    let new_trip_id = create_version(&mut tx, &r.trip, &trip_stations).await?;
+
+    segments::store(&mut tx, &fetched).await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| Error::Database(format!("error committing transaction: {e}")))?;
+
+    Ok(Json(UnmergeRoutesResponse {
+        route: new_trip_id,
+        stations: trip_stations.into_iter().map(|(id, _)| id).collect(),
+    }))
+}
+
+pub async fn preview_merge_routes(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    State(defaults): State<planner::Limits>,
+    Json(r): Json<MergeRoutesRequest>,
+) -> Result<Json<MergePreviewResponse>> {
+    check_unique(&r.requests)?;
+    check_trip_open(&pool, &r.trip).await?;
+    let requests = open_requests(&pool, &r.requests).await?;
+
+    let mut conn = pool.acquire().await?;
+    let assignments = get_assignments(&mut conn, &requests, false).await?;
+    drop(conn);
+
+    check_assignments(&r.trip, &assignments, r.reassign)?;
+    check_latest(&pool, &r.trip).await?;
+
+    let old_trip_stations = paths::get(&pool, &r.trip).await?;
+    let plan = plan_merge(&pool, &client, defaults.objective, &r.trip, &requests).await?;
+    let trip_stations = plan.stations;
+
+    // The added cost needs the legs of the trip as it is, too.
+    let mut conn = pool.acquire().await?;
+    for stations in [&old_trip_stations, &trip_stations] {
+        let fetched = segments::acquire(&pool, &client, stations).await?;
+        segments::store(&mut conn, &fetched).await?;
     }
 
-    for i in 0..trip_stations.len() - 1 {
-        let s1 = trip_stations[i].0;
-        let s2 = trip_stations[i + 1].0;
-
-        let existing: Option<(i32, i32)> =
-            sqlx::query_as("SELECT distance, time FROM segment WHERE s1 = $1 AND s2 = $2;")
-                .bind(s1)
-                .bind(s2)
-                .fetch_optional(&mut *tx)
-                .await?;
-
-        if existing.is_none() {
-            let s1_coords: PgPoint = sqlx::query_scalar("SELECT coords FROM station WHERE id = $1;")
-                .bind(s1)
-                .fetch_one(&mut *tx)
-                .await?;
-
-            let s2_coords: PgPoint = sqlx::query_scalar("SELECT coords FROM station WHERE id = $1;")
-                .bind(s2)
-                .fetch_one(&mut *tx)
-                .await?;
-
-            let route = client
-                .create_route(map_service::CreateRouteRequest {
-                    stops: vec![[s1_coords.x, s1_coords.y], [s2_coords.x, s2_coords.y]],
-                })
-                .await
-                .map_err(|e| ErrorResponse::new(format!("map service returned error: {e}")))?;
-
-            sqlx::query(
-                "INSERT INTO segment (s1, s2, points, distance, time)
-                VALUES ($1, $2, $3, $4, $5);",
-            )
-            .bind(s1)
-            .bind(s2)
-            .bind(
-                route
-                    .way
-                    .into_iter()
-                    .map(|[x, y]| PgPoint { x, y })
-                    .collect::<Vec<_>>(),
-            )
-            .bind(route.distance as i32)
-            .bind(route.duration as i32)
-            .execute(&mut *tx)
-            .await?;
+    let old_stations: Vec<Uuid> = old_trip_stations.iter().map(|(id, _)| *id).collect();
+    let stations: Vec<Uuid> = trip_stations.iter().map(|(id, _)| *id).collect();
+    let old_legs = segments::along(&pool, &old_stations).await?;
+    let new_legs = segments::along(&pool, &stations).await?;
+
+    let total = |legs: &[segments::Segment]| {
+        legs.iter().fold((0, 0), |(distance, time), leg| {
+            (distance + leg.distance as i64, time + leg.time as i64)
+        })
+    };
+    let (old_distance, old_time) = total(&old_legs);
+    let (distance, time) = total(&new_legs);
+
+    // Stops at the same station as the one before them take no travel.
+    let mut times = new_legs.iter().map(|leg| leg.time as f64);
+    let leg_times: Vec<f64> = plan
+        .stops
+        .windows(2)
+        .map(|pair| {
+            if pair[0].station() == pair[1].station() {
+                0.0
+            } else {
+                times.next().unwrap_or(0.0)
+            }
+        })
+        .collect();
+
+    // A station with several stops on it is served once the last of them is.
+    let mut arrivals: Vec<(Uuid, i64)> = plan
+        .stops
+        .iter()
+        .map(planner::Stop::station)
+        .zip(
+            plan.constraints
+                .schedule
+                .arrivals(&plan.stops, leg_times)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|t| t.round() as i64),
+        )
+        .collect();
+    arrivals.dedup_by(|later, earlier| {
+        let same = later.0 == earlier.0;
+        if same {
+            earlier.1 = later.1;
         }
-    }
+        same
+    });
+    let arrivals = arrivals.into_iter().map(|(_, t)| t).collect();
+
+    Ok(Json(MergePreviewResponse {
+        stations,
+        legs: new_legs
+            .iter()
+            .map(|leg| MergePreviewLeg {
+                from_station: leg.s1,
+                to_station: leg.s2,
+                distance: leg.distance as u64,
+                trip_time: leg.time as u64,
+            })
+            .collect(),
+        distance: distance as u64,
+        trip_time: time as u64,
+        added_distance: distance - old_distance,
+        added_time: time - old_time,
+        arrivals,
+        points: new_legs
+            .into_iter()
+            .flat_map(|leg| leg.points)
+            .map(Coord::to_lat_lon)
+            .collect(),
+    }))
+}
 
-    for request in &r.requests {
-        sqlx::query("UPDATE request SET trip_id = $1 WHERE id = $2;")
-            .bind(&new_trip_id)
-            .bind(request)
-            .execute(&mut *tx)
+pub async fn remove_stations(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    Json(r): Json<RemoveStationsRequest>,
+) -> Result<Json<RemoveStationsResponse>> {
+    let endpoints: Option<(Uuid, Uuid)> =
+        sqlx::query_as("SELECT source, destination FROM trip WHERE id = $1;")
+            .bind(r.trip)
+            .fetch_optional(&pool)
             .await?;
+
+    let Some((source, destination)) = endpoints else {
+        return Err(Error::NotFound(format!(
+            "cannot find trip with id {}",
+            r.trip
+        )));
+    };
+
+    if let Some(id) = r
+        .delete_stations
+        .iter()
+        .find(|id| **id == source || **id == destination)
+    {
+        return Err(Error::Validation(format!(
+            "station {} is an endpoint of trip {} and cannot be removed",
+            id, r.trip
+        )));
     }
 
+    let old_stations = paths::get(&pool, &r.trip).await?;
+
+    if let Some(id) = r
+        .delete_stations
+        .iter()
+        .find(|id| !old_stations.iter().any(|(station, _)| station == *id))
+    {
+        return Err(Error::Validation(format!(
+            "station {} is not on trip {}",
+            id, r.trip
+        )));
+    }
+
+    let requests: Vec<(Uuid, Uuid, Uuid)> = sqlx::query_as(
+        "SELECT id, source, destination FROM request WHERE trip_id = $1 ORDER BY id;",
+    )
+    .bind(r.trip)
+    .fetch_all(&pool)
+    .await?;
+
+    let detached: Vec<Uuid> = requests
+        .iter()
+        .filter(|(_, from, to)| r.delete_stations.contains(from) || r.delete_stations.contains(to))
+        .map(|(id, _, _)| *id)
+        .collect();
+
+    check_detachable(&pool, &detached).await?;
+
+    // Detached cargo requests take their other station along too.
+    let kept: Vec<(Uuid, Coord)> = old_stations
+        .iter()
+        .filter(|(id, _)| !r.delete_stations.contains(id))
+        .copied()
+        .collect();
+    let mut trip_stations = paths::without_requests(&pool, &r.trip, &kept, &detached).await?;
+    trip_stations.dedup_by(|a, b| a.0 == b.0);
+
+    let fetched = segments::acquire(&pool, &client, &trip_stations).await?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    paths::lock(&mut tx, &r.trip, &old_stations).await?;
+    check_trip_open(&mut *tx, &r.trip).await?;
+    check_latest(&mut *tx, &r.trip).await?;
+
+    let current: Vec<(Uuid, Uuid, Uuid)> = sqlx::query_as(
+        "SELECT id, source, destination FROM request WHERE trip_id = $1 ORDER BY id FOR UPDATE;",
+    )
+    .bind(r.trip)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if current != requests {
+        return Err(Error::Conflict(format!(
+            "cargo requests on trip {} were changed concurrently, retry the request",
+            r.trip
+        )));
+    }
+
+    check_detachable(&mut *tx, &detached).await?;
+
+    sqlx::query(
+        "UPDATE request
+        SET trip_id = NULL, status = CASE WHEN status = 'planned' THEN 'draft' ELSE status END
+        WHERE id = ANY($1);",
+    )
+    .bind(&detached)
+    .execute(&mut *tx)
+    .await?;
+
+    let new_trip_id = create_version(&mut tx, &r.trip, &trip_stations).await?;
+
+    segments::store(&mut tx, &fetched).await?;
+
     tx.commit()
         .await
-        .map_err(|e| ErrorResponse::new(format!("error committing transaction: {e}")))?;
+        .map_err(|e| Error::Database(format!("error committing transaction: {e}")))?;
 
-    Ok(Json(MergeRoutesResponse { route: new_trip_id }))
+    Ok(Json(RemoveStationsResponse {
+        route: new_trip_id,
+        detached_requests: detached,
+    }))
+}
+
+pub async fn get_nearest_stations(
+    State(pool): State<sqlx::PgPool>,
+    State(storage): State<spatial::Storage>,
+    Query(r): Query<GetNearestStationsRequest>,
+) -> Result<Json<GetNearestStationsResponse>> {
+    let point = Coord::new(r.lat, r.lon);
+    point.check()?;
+
+    if r.limit < 1 {
+        return Err(Error::Validation("limit must be positive".to_string()));
+    }
+
+    let stations = spatial::nearest_stations(&pool, storage, point, r.limit).await?;
+
+    Ok(Json(GetNearestStationsResponse {
+        stations: stations
+            .into_iter()
+            .map(|(id, address, coords, distance)| NearestStation {
+                station: Station {
+                    id,
+                    address,
+                    coords,
+                },
+                distance,
+            })
+            .collect(),
+    }))
+}
+
+pub async fn get_trip_corridor(
+    State(pool): State<sqlx::PgPool>,
+    State(storage): State<spatial::Storage>,
+    Path(path): Path<GetCorridorPath>,
+    Query(r): Query<GetCorridorRequest>,
+) -> Result<Json<GetCorridorResponse>> {
+    if !(r.width >= 0.0 && r.width.is_finite()) {
+        return Err(Error::Validation(
+            "width must be a non-negative number of meters".to_string(),
+        ));
+    }
+
+    let requests = spatial::requests_in_corridor(&pool, storage, &path.id, r.width).await?;
+
+    Ok(Json(GetCorridorResponse { requests }))
+}
+
+pub async fn get_station(
+    State(pool): State<sqlx::PgPool>,
+    Path(r): Path<GetStationRequest>,
+) -> Result<Json<GetStationResponse>> {
+    let Some(station) = stations::get(&pool, &[r.id]).await?.remove(&r.id) else {
+        return Err(Error::NotFound(format!(
+            "cannot find station with id {}",
+            r.id
+        )));
+    };
+
+    Ok(Json(GetStationResponse { station }))
+}
+
+pub async fn list_stations(
+    State(pool): State<sqlx::PgPool>,
+    Query(r): Query<ListStationsRequest>,
+) -> Result<Json<ListStationsResponse>> {
+    r.check()?;
+
+    let pattern = r.q.as_deref().map(|q| {
+        let escaped = q
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{escaped}%")
+    });
+
+    let filter = "($1::float8 IS NULL OR coords[1] BETWEEN $1 AND $3)
+        AND ($2::float8 IS NULL OR coords[0] BETWEEN $2 AND $4)
+        AND ($5::text IS NULL OR address ILIKE $5)";
+
+    let total: i64 = sqlx::query_scalar(&format!("SELECT count(*) FROM station WHERE {filter};"))
+        .bind(r.min_lat)
+        .bind(r.min_lon)
+        .bind(r.max_lat)
+        .bind(r.max_lon)
+        .bind(&pattern)
+        .fetch_one(&pool)
+        .await?;
+
+    let stations: Vec<(Uuid, String, Coord)> = sqlx::query_as(&format!(
+        "SELECT id, address, coords
+        FROM station
+        WHERE {filter}
+        ORDER BY address, id
+        LIMIT $6 OFFSET $7;"
+    ))
+    .bind(r.min_lat)
+    .bind(r.min_lon)
+    .bind(r.max_lat)
+    .bind(r.max_lon)
+    .bind(&pattern)
+    .bind(r.limit)
+    .bind(r.offset)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(ListStationsResponse {
+        stations: stations
+            .into_iter()
+            .map(|(id, address, coords)| Station {
+                id,
+                address,
+                coords,
+            })
+            .collect(),
+        total,
+    }))
+}
+
+pub async fn put_station(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    Path(path): Path<GetStationRequest>,
+    Json(r): Json<PutStationRequest>,
+) -> Result<Json<GetStationResponse>> {
+    let station = Station {
+        id: path.id,
+        address: r.address,
+        coords: r.coords,
+    };
+    station.check()?;
+
+    stations::upsert(&pool, &client, &[("", &station)]).await?;
+
+    Ok(Json(GetStationResponse { station }))
+}
+
+pub async fn delete_station(
+    State(pool): State<sqlx::PgPool>,
+    Path(r): Path<GetStationRequest>,
+) -> Result<StatusCode> {
+    stations::delete(&pool, &r.id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn upsert_stations(
+    State(pool): State<sqlx::PgPool>,
+    State(client): State<map_service::Client>,
+    Json(r): Json<UpsertStationsRequest>,
+) -> Result<Json<UpsertStationsResponse>> {
+    r.check()?;
+
+    let targets: Vec<String> = (0..r.stations.len())
+        .map(|index| format!("stations[{index}]"))
+        .collect();
+    let incoming: Vec<(&str, &Station)> = targets
+        .iter()
+        .map(String::as_str)
+        .zip(&r.stations)
+        .collect();
+
+    let resolved = stations::upsert(&pool, &client, &incoming).await?;
+
+    let mut response = UpsertStationsResponse {
+        created: Vec::new(),
+        updated: Vec::new(),
+        unchanged: Vec::new(),
+    };
+
+    for station in resolved {
+        let ids = match station.stored {
+            None => &mut response.created,
+            Some(_) if station.is_update() => &mut response.updated,
+            Some(_) => &mut response.unchanged,
+        };
+        ids.push(station.station.id);
+    }
+
+    Ok(Json(response))
 }
@@ -0,0 +1,171 @@
+use axum::Json;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::response::{IntoResponse, Response};
+use reqwest::StatusCode;
+
+use super::types::{ErrorDetail, ErrorResponse};
+
+/// Errors returned by the service endpoints.
+///
+/// Every variant maps to its own status code and a machine-readable `code`,
+/// so callers can tell client errors from retryable ones.
+#[derive(Debug)]
+pub enum Error {
+    /// The requested trip, cargo request or station does not exist.
+    NotFound(String),
+    /// The request is well-formed but cannot be applied.
+    Validation(String),
+    /// Some fields of the request hold malformed values.
+    InvalidInput(Vec<ErrorDetail>),
+    /// The request body cannot be read or parsed at all.
+    BadRequest(String),
+    /// The map service failed or returned an unusable response.
+    Upstream(String),
+    /// Some of the segments a route needs could not be fetched from the map service.
+    SegmentsUnavailable(Vec<ErrorDetail>),
+    /// The database is unavailable or a query failed.
+    Database(String),
+    /// The request conflicts with the current state of the data.
+    Conflict(String),
+    /// The service itself is broken, e.g. stored data cannot be decoded.
+    Internal(String),
+}
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Validation(_) | Error::InvalidInput(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Upstream(_) | Error::SegmentsUnavailable(_) => StatusCode::BAD_GATEWAY,
+            Error::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::BadRequest(_) => "bad_request",
+            Error::Validation(_) | Error::InvalidInput(_) => "validation_failed",
+            Error::Upstream(_) => "upstream_failed",
+            Error::SegmentsUnavailable(_) => "segments_unavailable",
+            Error::Database(_) => "database_failed",
+            Error::Conflict(_) => "conflict",
+            Error::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Error::NotFound(m)
+            | Error::BadRequest(m)
+            | Error::Validation(m)
+            | Error::Upstream(m)
+            | Error::Database(m)
+            | Error::Conflict(m)
+            | Error::Internal(m) => m.clone(),
+            Error::SegmentsUnavailable(failures) => {
+                format!("map service failed to return {} segment(s)", failures.len())
+            }
+            Error::InvalidInput(problems) => {
+                format!("request has {} invalid field(s)", problems.len())
+            }
+        }
+    }
+
+    pub fn details(&self) -> &[ErrorDetail] {
+        match self {
+            Error::SegmentsUnavailable(failures) | Error::InvalidInput(failures) => failures,
+            _ => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        if status.is_server_error() {
+            log::error!("{self}");
+        }
+
+        let body = ErrorResponse {
+            code: self.code().to_string(),
+            message: self.message(),
+            details: self.details().to_vec(),
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(value: sqlx::Error) -> Self {
+        const UNIQUE_VIOLATION: &str = "23505";
+        const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+        match &value {
+            sqlx::Error::Database(e) => match e.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => return Error::Conflict(format!("db conflict: {e}")),
+                Some(FOREIGN_KEY_VIOLATION) => {
+                    return Error::Validation(format!("db reference error: {e}"));
+                }
+                _ => {}
+            },
+            sqlx::Error::RowNotFound => {
+                return Error::NotFound("requested row was not found".to_string());
+            }
+            sqlx::Error::Decode(_)
+            | sqlx::Error::ColumnDecode { .. }
+            | sqlx::Error::ColumnNotFound(_)
+            | sqlx::Error::ColumnIndexOutOfBounds { .. }
+            | sqlx::Error::TypeNotFound { .. } => {
+                return Error::Internal(format!("cannot decode db row: {value}"));
+            }
+            _ => {}
+        }
+
+        Error::Database(format!("db returned error: {value}"))
+    }
+}
+
+fn invalid(target: &str, message: String) -> Error {
+    Error::InvalidInput(vec![ErrorDetail {
+        target: target.to_string(),
+        message,
+    }])
+}
+
+impl From<JsonRejection> for Error {
+    fn from(value: JsonRejection) -> Self {
+        match value {
+            JsonRejection::JsonDataError(e) => invalid("body", e.body_text()),
+            e => Error::BadRequest(e.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for Error {
+    fn from(value: PathRejection) -> Self {
+        match value {
+            PathRejection::FailedToDeserializePathParams(e) => invalid("path", e.body_text()),
+            e => Error::Internal(e.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(value: QueryRejection) -> Self {
+        invalid("query", value.body_text())
+    }
+}
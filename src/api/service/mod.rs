@@ -1,10 +1,17 @@
 pub mod endpoints;
+pub mod error;
+pub mod extract;
+pub mod paths;
+pub mod planner;
 pub mod router;
+pub mod segments;
+pub mod spatial;
+pub mod stations;
+pub mod status;
 pub mod types;
+pub mod validation;
 
-use axum::Json;
-use axum::response::{IntoResponse, Response};
-use reqwest::StatusCode;
+pub use error::Error;
 
 use crate::api::map_service;
 use crate::db;
@@ -13,11 +20,23 @@ use crate::db;
 pub struct State {
     pub db: db::Database,
     pub client: map_service::client::Client,
+    pub limits: planner::Limits,
+    pub storage: spatial::Storage,
 }
 
 impl State {
-    pub fn new(db: crate::db::Database, client: map_service::client::Client) -> Self {
-        Self { db, client }
+    pub fn new(
+        db: crate::db::Database,
+        client: map_service::client::Client,
+        limits: planner::Limits,
+        storage: spatial::Storage,
+    ) -> Self {
+        Self {
+            db,
+            client,
+            limits,
+            storage,
+        }
     }
 }
 
@@ -33,22 +52,14 @@ impl axum::extract::FromRef<State> for sqlx::PgPool {
     }
 }
 
-impl IntoResponse for types::ErrorResponse {
-    fn into_response(self) -> Response {
-        (StatusCode::BAD_REQUEST, Json(self)).into_response()
-    }
-}
-
-impl types::ErrorResponse {
-    fn new(message: impl Into<String>) -> Self {
-        Self {
-            message: message.into(),
-        }
+impl axum::extract::FromRef<State> for planner::Limits {
+    fn from_ref(input: &State) -> Self {
+        input.limits.clone()
     }
 }
 
-impl From<sqlx::Error> for types::ErrorResponse {
-    fn from(value: sqlx::Error) -> Self {
-        types::ErrorResponse::new(format!("db returned error: {value}"))
+impl axum::extract::FromRef<State> for spatial::Storage {
+    fn from_ref(input: &State) -> Self {
+        input.storage
     }
 }
@@ -0,0 +1,129 @@
+//! Reading and rewriting the station order (`path`) of trips.
+
+use uuid::Uuid;
+
+use crate::types::Coord;
+
+use super::Error;
+use super::endpoints::Result;
+
+/// Returns the stations of a trip in order. Empty if the trip does not exist.
+pub async fn get(pool: &sqlx::PgPool, trip: &Uuid) -> Result<Vec<(Uuid, Coord)>> {
+    let stations = sqlx::query_as(
+        "SELECT s.id, s.coords
+        FROM path p
+        INNER JOIN station s ON p.station_id = s.id
+        WHERE p.trip_id = $1
+        ORDER BY p.index;",
+    )
+    .bind(trip)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(stations)
+}
+
+/// Returns the stations of `trip` once the `leaving` cargo requests are
+/// detached from it. A station is removed unless it is one of the trip's own
+/// endpoints or another cargo request on the trip still uses it. Visits of
+/// the same station left next to each other are merged into one.
+pub async fn without_requests(
+    pool: &sqlx::PgPool,
+    trip: &Uuid,
+    stations: &[(Uuid, Coord)],
+    leaving: &[Uuid],
+) -> Result<Vec<(Uuid, Coord)>> {
+    let endpoints: Option<(Uuid, Uuid)> =
+        sqlx::query_as("SELECT source, destination FROM trip WHERE id = $1;")
+            .bind(trip)
+            .fetch_optional(pool)
+            .await?;
+
+    let Some((source, destination)) = endpoints else {
+        return Err(Error::NotFound(format!(
+            "cannot find trip with id {}",
+            trip
+        )));
+    };
+
+    let requests: Vec<(Uuid, Uuid, bool)> = sqlx::query_as(
+        "SELECT source, destination, id = ANY($2)
+        FROM request
+        WHERE trip_id = $1;",
+    )
+    .bind(trip)
+    .bind(leaving)
+    .fetch_all(pool)
+    .await?;
+
+    let used_by = |leaving: bool| -> Vec<Uuid> {
+        requests
+            .iter()
+            .filter(|r| r.2 == leaving)
+            .flat_map(|r| [r.0, r.1])
+            .collect()
+    };
+
+    let removed: Vec<Uuid> = used_by(true)
+        .into_iter()
+        .filter(|id| *id != source && *id != destination && !used_by(false).contains(id))
+        .collect();
+
+    let mut stations: Vec<(Uuid, Coord)> = stations
+        .iter()
+        .filter(|(id, _)| !removed.contains(id))
+        .copied()
+        .collect();
+    stations.dedup_by(|a, b| a.0 == b.0);
+
+    Ok(stations)
+}
+
+/// Locks `trip` for the rest of the transaction and checks its stations are
+/// still `expected`, failing with a conflict if it was changed meanwhile.
+pub async fn lock(
+    tx: &mut sqlx::PgConnection,
+    trip: &Uuid,
+    expected: &[(Uuid, Coord)],
+) -> Result<()> {
+    sqlx::query("SELECT 1 FROM trip WHERE id = $1 FOR UPDATE;")
+        .bind(trip)
+        .execute(&mut *tx)
+        .await?;
+
+    let current: Vec<Uuid> =
+        sqlx::query_scalar("SELECT station_id FROM path WHERE trip_id = $1 ORDER BY index;")
+            .bind(trip)
+            .fetch_all(&mut *tx)
+            .await?;
+
+    if !current.iter().eq(expected.iter().map(|(id, _)| id)) {
+        return Err(Error::Conflict(format!(
+            "trip {} was changed concurrently, retry the request",
+            trip
+        )));
+    }
+
+    Ok(())
+}
+
+/// Writes the stations of a trip that has none yet.
+pub async fn insert(
+    tx: &mut sqlx::PgConnection,
+    trip: &Uuid,
+    stations: &[(Uuid, Coord)],
+) -> Result<()> {
+    for (index, (station, _)) in stations.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO path (trip_id, station_id, index)
+            VALUES ($1, $2, $3);",
+        )
+        .bind(trip)
+        .bind(station)
+        .bind(index as i32)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1050 @@
+//! Cost model and insertion of cargo requests into trips.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::types::Coord;
+
+/// Speed used to estimate travel time when only the great-circle distance
+/// between two stations is known, in m/s (50 km/h).
+const FALLBACK_SPEED: f64 = 50.0 / 3.6;
+
+/// Upper bound on local search rounds in [`improve`] and [`select`].
+const MAX_IMPROVEMENT_ROUNDS: usize = 100;
+
+/// Where the distance and time of a candidate came from.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    /// Road distance and time of cached segments.
+    Road,
+    /// Great-circle distance between stations.
+    Haversine,
+    /// Some legs by road, some great-circle.
+    Mixed,
+}
+
+impl Metric {
+    fn combine(self, other: Metric) -> Metric {
+        if self == other { self } else { Metric::Mixed }
+    }
+}
+
+/// What insertion and ranking of candidates minimise.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Objective {
+    #[default]
+    Distance,
+    Time,
+}
+
+impl std::str::FromStr for Objective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "distance" => Ok(Objective::Distance),
+            "time" => Ok(Objective::Time),
+            _ => Err(format!("unknown objective {s}, expected distance or time")),
+        }
+    }
+}
+
+/// Limits a candidate's detour has to stay within. `None` means unlimited.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Added distance in meters.
+    pub max_added_distance: Option<f64>,
+    /// Added time in seconds.
+    pub max_added_time: Option<f64>,
+    /// Added distance in percent of the trip's own distance.
+    pub max_detour_percent: Option<f64>,
+    pub objective: Objective,
+}
+
+impl Limits {
+    /// Whether a trip of cost `base` may grow by the given distance and time.
+    pub fn allows(&self, base: &Cost, added_distance: f64, added_time: f64) -> bool {
+        let within = |value: f64, max: Option<f64>| max.is_none_or(|max| value <= max);
+
+        let percent = if base.distance > 0.0 {
+            added_distance / base.distance * 100.0
+        } else {
+            0.0
+        };
+
+        within(added_distance, self.max_added_distance)
+            && within(added_time, self.max_added_time)
+            && within(percent, self.max_detour_percent)
+    }
+}
+
+/// Distance in meters and time in seconds of one or more legs.
+#[derive(Clone, Copy, Debug)]
+pub struct Cost {
+    pub distance: f64,
+    pub time: f64,
+    pub metric: Metric,
+}
+
+impl Cost {
+    pub fn value(&self, objective: Objective) -> f64 {
+        match objective {
+            Objective::Distance => self.distance,
+            Objective::Time => self.time,
+        }
+    }
+
+    fn add(self, other: Cost) -> Cost {
+        Cost {
+            distance: self.distance + other.distance,
+            time: self.time + other.time,
+            metric: self.metric.combine(other.metric),
+        }
+    }
+}
+
+/// Travel costs between stations: road distance and time where a segment is
+/// cached, great-circle distance otherwise.
+#[derive(Default)]
+pub struct Costs {
+    coords: HashMap<Uuid, Coord>,
+    road: HashMap<(Uuid, Uuid), (f64, f64)>,
+}
+
+impl Costs {
+    pub fn add_station(&mut self, id: Uuid, coords: Coord) {
+        self.coords.insert(id, coords);
+    }
+
+    pub fn add_segment(&mut self, s1: Uuid, s2: Uuid, distance: f64, time: f64) {
+        self.road.insert((s1, s2), (distance, time));
+    }
+
+    pub fn coords(&self, id: Uuid) -> Coord {
+        self.coords[&id]
+    }
+
+    pub fn has_segment(&self, s1: Uuid, s2: Uuid) -> bool {
+        s1 == s2 || self.road.contains_key(&(s1, s2))
+    }
+
+    pub fn leg(&self, s1: Uuid, s2: Uuid) -> Cost {
+        if s1 == s2 {
+            return Cost {
+                distance: 0.0,
+                time: 0.0,
+                metric: Metric::Road,
+            };
+        }
+
+        if let Some((distance, time)) = self.road.get(&(s1, s2)) {
+            return Cost {
+                distance: *distance,
+                time: *time,
+                metric: Metric::Road,
+            };
+        }
+
+        let distance = distance(&self.coords[&s1], &self.coords[&s2]);
+
+        Cost {
+            distance,
+            time: distance / FALLBACK_SPEED,
+            metric: Metric::Haversine,
+        }
+    }
+
+    pub fn route(&self, stations: &[Uuid]) -> Cost {
+        stations
+            .windows(2)
+            .map(|pair| self.leg(pair[0], pair[1]))
+            .reduce(Cost::add)
+            .unwrap_or(Cost {
+                distance: 0.0,
+                time: 0.0,
+                metric: Metric::Road,
+            })
+    }
+}
+
+/// A station on a planned trip.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Stop {
+    /// Station already on the trip, at `index` in its path. A station the
+    /// trip visits twice is two stops.
+    Fixed {
+        station: Uuid,
+        index: usize,
+    },
+    Pickup {
+        request: Uuid,
+        station: Uuid,
+    },
+    Dropoff {
+        request: Uuid,
+        station: Uuid,
+    },
+}
+
+impl Stop {
+    pub fn station(&self) -> Uuid {
+        match self {
+            Stop::Fixed { station, .. }
+            | Stop::Pickup { station, .. }
+            | Stop::Dropoff { station, .. } => *station,
+        }
+    }
+
+    pub fn request(&self) -> Option<Uuid> {
+        match self {
+            Stop::Fixed { .. } => None,
+            Stop::Pickup { request, .. } | Stop::Dropoff { request, .. } => Some(*request),
+        }
+    }
+}
+
+pub fn stations(stops: &[Stop]) -> Vec<Uuid> {
+    stops.iter().map(Stop::station).collect()
+}
+
+/// Stops of a trip's path.
+pub fn fixed(path: &[Uuid]) -> Vec<Stop> {
+    path.iter()
+        .enumerate()
+        .map(|(index, station)| Stop::Fixed {
+            station: *station,
+            index,
+        })
+        .collect()
+}
+
+/// Stops of `trip` a cargo request from `source` to `destination` rides
+/// between: the first visit of `source` and the first visit of `destination`
+/// after it. Returns `None` if the trip does not go from one to the other.
+pub fn visits(trip: &[Stop], source: Uuid, destination: Uuid) -> Option<(Stop, Stop)> {
+    let pickup = trip.iter().position(|stop| stop.station() == source)?;
+    let dropoff = trip[pickup + 1..]
+        .iter()
+        .find(|stop| stop.station() == destination)?;
+
+    Some((trip[pickup], *dropoff))
+}
+
+/// Time a stop may be served in, in seconds since the Unix epoch.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Window {
+    pub earliest: Option<f64>,
+    pub latest: Option<f64>,
+}
+
+impl Window {
+    /// The part of both windows.
+    fn intersect(self, other: Window) -> Window {
+        Window {
+            earliest: max(self.earliest, other.earliest),
+            latest: min(self.latest, other.latest),
+        }
+    }
+}
+
+fn max(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn min(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Departure of a trip and the time windows of its stops. Without a
+/// departure time no arrival times are known and every window is met.
+#[derive(Default)]
+pub struct Schedule {
+    departure: Option<f64>,
+    windows: HashMap<Stop, Window>,
+}
+
+impl Schedule {
+    pub fn new(departure: Option<f64>) -> Self {
+        Self {
+            departure,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Restricts `stop` to `window`, on top of any window it already has.
+    pub fn add_window(&mut self, stop: Stop, window: Window) {
+        let window = match self.windows.get(&stop) {
+            Some(existing) => existing.intersect(window),
+            None => window,
+        };
+
+        self.windows.insert(stop, window);
+    }
+
+    /// Whether the departure is known and any stop has a window, so that
+    /// arrival times matter.
+    pub fn has_windows(&self) -> bool {
+        self.departure.is_some() && !self.windows.is_empty()
+    }
+
+    /// Time every stop is served at, given the travel times between
+    /// consecutive stops. Arriving before a window opens means waiting for it.
+    pub fn arrivals(
+        &self,
+        stops: &[Stop],
+        legs: impl IntoIterator<Item = f64>,
+    ) -> Option<Vec<f64>> {
+        let mut time = self.departure?;
+        let mut legs = legs.into_iter();
+        let mut arrivals = Vec::with_capacity(stops.len());
+
+        for (i, stop) in stops.iter().enumerate() {
+            if i > 0 {
+                time += legs.next().unwrap_or(0.0);
+            }
+
+            if let Some(earliest) = self.windows.get(stop).and_then(|w| w.earliest) {
+                time = time.max(earliest);
+            }
+
+            arrivals.push(time);
+        }
+
+        Some(arrivals)
+    }
+
+    /// Arrival times along `stops` with travel times from `costs`.
+    pub fn arrivals_by(&self, costs: &Costs, stops: &[Stop]) -> Option<Vec<f64>> {
+        let legs = stops
+            .windows(2)
+            .map(|pair| costs.leg(pair[0].station(), pair[1].station()).time);
+
+        self.arrivals(stops, legs)
+    }
+
+    /// Whether every stop is served before its window closes.
+    pub fn feasible(&self, costs: &Costs, stops: &[Stop]) -> bool {
+        let Some(arrivals) = self.arrivals_by(costs, stops) else {
+            return true;
+        };
+
+        stops.iter().zip(arrivals).all(|(stop, arrival)| {
+            self.windows
+                .get(stop)
+                .and_then(|w| w.latest)
+                .is_none_or(|latest| arrival <= latest)
+        })
+    }
+}
+
+/// Amount of cargo: weight in kilograms, volume in cubic meters and pallets.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Load {
+    pub weight: f64,
+    pub volume: f64,
+    pub pallets: f64,
+}
+
+impl Load {
+    fn add(self, other: Load) -> Load {
+        Load {
+            weight: self.weight + other.weight,
+            volume: self.volume + other.volume,
+            pallets: self.pallets + other.pallets,
+        }
+    }
+
+    fn negate(self) -> Load {
+        Load {
+            weight: -self.weight,
+            volume: -self.volume,
+            pallets: -self.pallets,
+        }
+    }
+
+    fn fits(self, limit: Load) -> bool {
+        self.weight <= limit.weight + f64::EPSILON
+            && self.volume <= limit.volume + f64::EPSILON
+            && self.pallets <= limit.pallets + f64::EPSILON
+    }
+}
+
+/// What a trip's vehicle can carry and where cargo is loaded and unloaded.
+pub struct Capacity {
+    limit: Load,
+    changes: HashMap<Stop, Load>,
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Self::new(None, None, None)
+    }
+}
+
+impl Capacity {
+    /// Capacity of a vehicle. `None` means unlimited.
+    pub fn new(weight: Option<f64>, volume: Option<f64>, pallets: Option<f64>) -> Self {
+        Self {
+            limit: Load {
+                weight: weight.unwrap_or(f64::INFINITY),
+                volume: volume.unwrap_or(f64::INFINITY),
+                pallets: pallets.unwrap_or(f64::INFINITY),
+            },
+            changes: HashMap::new(),
+        }
+    }
+
+    /// Loads `load` at the `pickup` stop and unloads it at the `dropoff` stop.
+    pub fn add_cargo(&mut self, pickup: Stop, dropoff: Stop, load: Load) {
+        for (stop, change) in [(pickup, load), (dropoff, load.negate())] {
+            let total = self
+                .changes
+                .get(&stop)
+                .copied()
+                .unwrap_or_default()
+                .add(change);
+            self.changes.insert(stop, total);
+        }
+    }
+
+    /// Load on board after every stop.
+    pub fn profile(&self, stops: &[Stop]) -> Vec<Load> {
+        stops
+            .iter()
+            .scan(Load::default(), |load, stop| {
+                *load = load.add(self.changes.get(stop).copied().unwrap_or_default());
+                Some(*load)
+            })
+            .collect()
+    }
+
+    /// Whether the load never exceeds the capacity along `stops`.
+    pub fn feasible(&self, stops: &[Stop]) -> bool {
+        self.profile(stops)
+            .into_iter()
+            .all(|load| load.fits(self.limit))
+    }
+}
+
+/// What every planned station order has to respect.
+#[derive(Default)]
+pub struct Constraints {
+    pub schedule: Schedule,
+    pub capacity: Capacity,
+}
+
+impl Constraints {
+    pub fn feasible(&self, costs: &Costs, stops: &[Stop]) -> bool {
+        self.capacity.feasible(stops) && self.schedule.feasible(costs, stops)
+    }
+}
+
+/// Cargo request to be planned into a trip.
+#[derive(Clone, Copy, Debug)]
+pub struct Request {
+    pub id: Uuid,
+    pub pickup: Uuid,
+    pub dropoff: Uuid,
+}
+
+/// Result of inserting a cargo request into a trip.
+pub struct Insertion {
+    pub request: Uuid,
+    /// Index in the new station order the pickup was inserted at.
+    pub pickup: usize,
+    /// Index in the new station order the drop-off was inserted at.
+    pub dropoff: usize,
+    pub stops: Vec<Stop>,
+    pub added_distance: f64,
+    pub added_time: f64,
+    pub metric: Metric,
+}
+
+/// Inserts the request's pickup and drop-off at the pair of positions that
+/// adds the least to `objective`, the pickup before the drop-off, and keeps
+/// every stop within its time window and the load within the capacity. The
+/// trip's first and last stations stay in place. Returns `None` if no pair of
+/// positions meets the constraints.
+pub fn insert(
+    costs: &Costs,
+    constraints: &Constraints,
+    objective: Objective,
+    trip: &[Stop],
+    request: &Request,
+) -> Option<Insertion> {
+    let leg = |s1: Uuid, s2: Uuid| costs.leg(s1, s2).value(objective);
+    let detour = |pair: &[Stop], station: Uuid| {
+        leg(pair[0].station(), station) + leg(station, pair[1].station())
+            - leg(pair[0].station(), pair[1].station())
+    };
+
+    let pickup_detours: Vec<f64> = trip
+        .windows(2)
+        .map(|pair| detour(pair, request.pickup))
+        .collect();
+    let dropoff_detours: Vec<f64> = trip
+        .windows(2)
+        .map(|pair| detour(pair, request.dropoff))
+        .collect();
+
+    let mut positions = Vec::new();
+
+    for (i, pair) in trip.windows(2).enumerate() {
+        // Both between the same two stations: pickup right before drop-off.
+        let together = leg(pair[0].station(), request.pickup)
+            + leg(request.pickup, request.dropoff)
+            + leg(request.dropoff, pair[1].station())
+            - leg(pair[0].station(), pair[1].station());
+
+        positions.push((i, i, together));
+
+        for (j, dropoff_detour) in dropoff_detours.iter().enumerate().skip(i + 1) {
+            positions.push((i, j, pickup_detours[i] + dropoff_detour));
+        }
+    }
+
+    positions.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let stops_at = |pickup_after: usize, dropoff_after: usize| {
+        let mut stops = trip.to_vec();
+        stops.insert(
+            dropoff_after + 1,
+            Stop::Dropoff {
+                request: request.id,
+                station: request.dropoff,
+            },
+        );
+        stops.insert(
+            pickup_after + 1,
+            Stop::Pickup {
+                request: request.id,
+                station: request.pickup,
+            },
+        );
+        stops
+    };
+
+    let (pickup_after, dropoff_after, stops) = positions
+        .into_iter()
+        .map(|(i, j, _)| (i, j, stops_at(i, j)))
+        .find(|(_, _, stops)| constraints.feasible(costs, stops))?;
+
+    let before = costs.route(&stations(trip));
+    let after = costs.route(&stations(&stops));
+
+    Some(Insertion {
+        request: request.id,
+        pickup: pickup_after + 1,
+        dropoff: dropoff_after + 2,
+        stops,
+        added_distance: after.distance - before.distance,
+        added_time: after.time - before.time,
+        metric: after.metric,
+    })
+}
+
+/// Plans all `requests` into the trip.
+///
+/// The requests are inserted one at a time in several orders, each result is
+/// improved by [`improve`], and the cheapest one is returned. The orders are
+/// derived from the requests sorted by ID, so the order they are passed in does
+/// not matter. Stations already on the trip keep their relative order, its
+/// first and last stations stay in place. Returns `None` if no order meets
+/// the constraints.
+pub fn optimise(
+    costs: &Costs,
+    constraints: &Constraints,
+    objective: Objective,
+    trip: &[Stop],
+    requests: &[Request],
+) -> Option<Vec<Stop>> {
+    let direct = |r: &Request| costs.leg(r.pickup, r.dropoff).value(objective);
+
+    let mut requests = requests.to_vec();
+    requests.sort_by_key(|r| r.id);
+    let requests = requests.as_slice();
+
+    let mut longest_first = requests.to_vec();
+    longest_first.sort_by(|a, b| direct(b).total_cmp(&direct(a)));
+
+    let mut orders = vec![
+        requests.to_vec(),
+        requests.iter().rev().copied().collect(),
+        longest_first.clone(),
+        longest_first.into_iter().rev().collect(),
+    ];
+
+    // Insert first whichever request is cheapest to insert into the trip alone.
+    let alone = |r: &Request| {
+        insert(costs, constraints, objective, trip, r).map_or(f64::INFINITY, |i| i.added(objective))
+    };
+    let mut cheapest_first = requests.to_vec();
+    cheapest_first.sort_by(|a, b| alone(a).total_cmp(&alone(b)));
+    orders.push(cheapest_first);
+
+    orders
+        .into_iter()
+        .filter_map(|order| {
+            let stops = order.iter().try_fold(trip.to_vec(), |stops, request| {
+                insert(costs, constraints, objective, &stops, request).map(|i| i.stops)
+            })?;
+
+            Some(improve(costs, constraints, objective, stops))
+        })
+        .min_by(|a, b| {
+            costs
+                .route(&stations(a))
+                .value(objective)
+                .total_cmp(&costs.route(&stations(b)).value(objective))
+        })
+}
+
+/// Local search over the planned stops: moves single pickups and drop-offs
+/// to other positions and reverses runs of them (2-opt), as long as every
+/// pickup stays before its drop-off and the constraints are met. Keeps every
+/// change that lowers the cost.
+pub fn improve(
+    costs: &Costs,
+    constraints: &Constraints,
+    objective: Objective,
+    mut stops: Vec<Stop>,
+) -> Vec<Stop> {
+    let cost = |stops: &[Stop]| costs.route(&stations(stops)).value(objective);
+    let valid = |stops: &[Stop]| precedence_holds(stops) && constraints.feasible(costs, stops);
+    let movable = |stop: &Stop| !matches!(stop, Stop::Fixed { .. });
+
+    if stops.len() < 4 {
+        return stops;
+    }
+
+    let last = stops.len() - 1;
+
+    for _ in 0..MAX_IMPROVEMENT_ROUNDS {
+        let current = cost(&stops);
+        let mut improved = None;
+
+        'relocate: for from in 1..last {
+            if !movable(&stops[from]) {
+                continue;
+            }
+
+            for to in 1..last {
+                if to == from {
+                    continue;
+                }
+
+                let mut candidate = stops.clone();
+                let stop = candidate.remove(from);
+                candidate.insert(to, stop);
+
+                if cost(&candidate) < current - f64::EPSILON && valid(&candidate) {
+                    improved = Some(candidate);
+                    break 'relocate;
+                }
+            }
+        }
+
+        if improved.is_none() {
+            'reverse: for from in 1..last {
+                for to in from + 1..last {
+                    if !stops[from..=to].iter().all(movable) {
+                        break;
+                    }
+
+                    let mut candidate = stops.clone();
+                    candidate[from..=to].reverse();
+
+                    if cost(&candidate) < current - f64::EPSILON && valid(&candidate) {
+                        improved = Some(candidate);
+                        break 'reverse;
+                    }
+                }
+            }
+        }
+
+        match improved {
+            Some(candidate) => stops = candidate,
+            None => break,
+        }
+    }
+
+    stops
+}
+
+/// Whether every drop-off comes after the pickup of the same request.
+fn precedence_holds(stops: &[Stop]) -> bool {
+    let mut picked_up = Vec::new();
+
+    for stop in stops {
+        match stop {
+            Stop::Pickup { request, .. } => picked_up.push(*request),
+            Stop::Dropoff { request, .. } if !picked_up.contains(request) => return false,
+            _ => {}
+        }
+    }
+
+    true
+}
+
+impl Insertion {
+    pub fn added(&self, objective: Objective) -> f64 {
+        match objective {
+            Objective::Distance => self.added_distance,
+            Objective::Time => self.added_time,
+        }
+    }
+}
+
+/// Subset of cargo requests planned into a trip together.
+pub struct Selection {
+    pub requests: Vec<Uuid>,
+    pub stops: Vec<Stop>,
+    pub added_distance: f64,
+    pub added_time: f64,
+    pub metric: Metric,
+}
+
+/// Chooses a subset of `requests` that fits into the trip within `limits` as
+/// a whole and meets the constraints. This is a heuristic: the result is not
+/// guaranteed to be the largest or the cheapest possible subset.
+///
+/// Requests are inserted greedily, each time the one adding the least cost,
+/// until none fits any more. Then local search swaps a chosen request for one
+/// that was left out or re-inserts it elsewhere, keeping every change that
+/// lowers the cost and refilling after each swap.
+pub fn select(
+    costs: &Costs,
+    constraints: &Constraints,
+    limits: &Limits,
+    trip: &[Stop],
+    requests: &[Request],
+) -> Selection {
+    let objective = limits.objective;
+    let base = costs.route(&stations(trip));
+
+    let cost_of = |stops: &[Stop]| costs.route(&stations(stops));
+    let fits = |stops: &[Stop]| {
+        let cost = cost_of(stops);
+        limits.allows(&base, cost.distance - base.distance, cost.time - base.time)
+    };
+
+    let fill = |stops: &mut Vec<Stop>, chosen: &mut Vec<Uuid>| loop {
+        let best = requests
+            .iter()
+            .filter(|r| !chosen.contains(&r.id))
+            .filter_map(|r| insert(costs, constraints, objective, stops, r))
+            .filter(|insertion| fits(&insertion.stops))
+            .min_by(|a, b| a.added(objective).total_cmp(&b.added(objective)));
+
+        let Some(best) = best else {
+            break;
+        };
+
+        chosen.push(best.request);
+        *stops = best.stops;
+    };
+
+    let mut stops = trip.to_vec();
+    let mut chosen = Vec::new();
+    fill(&mut stops, &mut chosen);
+
+    for _ in 0..MAX_IMPROVEMENT_ROUNDS {
+        let current = cost_of(&stops).value(objective);
+        let mut improved = false;
+
+        'search: for out in chosen.clone() {
+            let without = remove(&stops, out);
+
+            let reinserted = requests
+                .iter()
+                .filter(|r| r.id == out || !chosen.contains(&r.id))
+                .filter_map(|r| insert(costs, constraints, objective, &without, r))
+                .filter(|insertion| fits(&insertion.stops));
+
+            for insertion in reinserted {
+                if cost_of(&insertion.stops).value(objective) < current - f64::EPSILON {
+                    chosen.retain(|id| *id != out);
+                    chosen.push(insertion.request);
+                    stops = insertion.stops;
+                    fill(&mut stops, &mut chosen);
+                    improved = true;
+                    break 'search;
+                }
+            }
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    let cost = cost_of(&stops);
+
+    Selection {
+        requests: chosen,
+        stops,
+        added_distance: cost.distance - base.distance,
+        added_time: cost.time - base.time,
+        metric: cost.metric,
+    }
+}
+
+/// Removes the pickup and drop-off of `request` from `stops`.
+pub fn remove(stops: &[Stop], request: Uuid) -> Vec<Stop> {
+    stops
+        .iter()
+        .filter(|stop| stop.request() != Some(request))
+        .copied()
+        .collect()
+}
+
+/// Great-circle distance between two stations in meters.
+pub fn distance(p1: &Coord, p2: &Coord) -> f64 {
+    const R: f64 = 6371000.0;
+
+    let lat1 = p1.lat.to_radians();
+    let lon1 = p1.lon.to_radians();
+    let lat2 = p2.lat.to_radians();
+    let lon2 = p2.lon.to_radians();
+
+    let dlat = lat2 - lat1;
+    let dlon = lon2 - lon1;
+
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+
+    R * 2.0 * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    /// Stations `1..=n` at the given latitudes and longitudes.
+    fn costs_at(points: &[(f64, f64)]) -> Costs {
+        let mut costs = Costs::default();
+        for (i, (lat, lon)) in points.iter().enumerate() {
+            costs.add_station(id(i as u128 + 1), Coord::new(*lat, *lon));
+        }
+        costs
+    }
+
+    /// Stations `1..=n` along a parallel, `lons[i - 1]` degrees east.
+    fn costs(lons: &[f64]) -> Costs {
+        costs_at(&lons.iter().map(|lon| (55.0, *lon)).collect::<Vec<_>>())
+    }
+
+    fn trip(stations: &[u128]) -> Vec<Stop> {
+        fixed(&stations.iter().map(|n| id(*n)).collect::<Vec<_>>())
+    }
+
+    fn request(n: u128, pickup: u128, dropoff: u128) -> Request {
+        Request {
+            id: id(100 + n),
+            pickup: id(pickup),
+            dropoff: id(dropoff),
+        }
+    }
+
+    fn pallet() -> Load {
+        Load {
+            weight: 0.0,
+            volume: 0.0,
+            pallets: 1.0,
+        }
+    }
+
+    fn pickup(r: &Request) -> Stop {
+        Stop::Pickup {
+            request: r.id,
+            station: r.pickup,
+        }
+    }
+
+    fn dropoff(r: &Request) -> Stop {
+        Stop::Dropoff {
+            request: r.id,
+            station: r.dropoff,
+        }
+    }
+
+    #[test]
+    fn pickup_comes_before_dropoff() {
+        let costs = costs(&[37.0, 38.0, 37.8, 37.2]);
+        // Travels against the trip's direction.
+        let r = request(1, 3, 4);
+
+        let insertion = insert(
+            &costs,
+            &Constraints::default(),
+            Objective::Distance,
+            &trip(&[1, 2]),
+            &r,
+        )
+        .unwrap();
+
+        assert!(insertion.pickup < insertion.dropoff);
+        assert_eq!(insertion.stops[insertion.pickup], pickup(&r));
+        assert_eq!(insertion.stops[insertion.dropoff], dropoff(&r));
+        assert!(precedence_holds(&insertion.stops));
+    }
+
+    #[test]
+    fn first_and_last_stations_stay() {
+        // Both requests lie beyond the trip's ends.
+        let costs = costs(&[37.0, 38.0, 36.5, 36.8, 38.2, 38.5]);
+        let requests = [request(1, 3, 4), request(2, 5, 6)];
+
+        let stops = optimise(
+            &costs,
+            &Constraints::default(),
+            Objective::Distance,
+            &trip(&[1, 2]),
+            &requests,
+        )
+        .unwrap();
+
+        assert_eq!(stops.len(), 6);
+        let ends = trip(&[1, 2]);
+        assert_eq!(stops[0], ends[0]);
+        assert_eq!(stops[5], ends[1]);
+        assert!(precedence_holds(&stops));
+    }
+
+    #[test]
+    fn request_order_does_not_matter() {
+        // Scattered so that the insertion orders tried lead to different plans.
+        let costs = costs_at(&[
+            (55.0, 37.0),
+            (55.0, 38.0),
+            (54.852, 37.886),
+            (55.1405, 37.148),
+            (54.7915, 37.524),
+            (55.225, 37.366),
+            (55.0995, 37.87),
+            (54.8095, 37.983),
+            (55.084, 37.574),
+            (54.7765, 37.302),
+        ]);
+        let requests = [
+            request(1, 3, 4),
+            request(2, 5, 6),
+            request(3, 7, 8),
+            request(4, 9, 10),
+        ];
+        let plan = |requests: &[Request]| {
+            optimise(
+                &costs,
+                &Constraints::default(),
+                Objective::Distance,
+                &trip(&[1, 2]),
+                requests,
+            )
+            .unwrap()
+        };
+
+        let expected = plan(&requests);
+
+        for order in [[3, 2, 1, 0], [1, 3, 0, 2], [2, 0, 3, 1]] {
+            let shuffled = order.map(|i| requests[i]);
+            assert_eq!(plan(&shuffled), expected);
+        }
+    }
+
+    #[test]
+    fn improve_keeps_constraints() {
+        let costs = costs(&[37.0, 38.0, 37.2, 37.6, 37.4, 37.8]);
+        let (a, b) = (request(1, 3, 4), request(2, 5, 6));
+
+        // Room for one pallet, so the requests cannot be interleaved, and the
+        // second one has to be delivered before the first is picked up.
+        let mut constraints = Constraints {
+            schedule: Schedule::new(Some(0.0)),
+            capacity: Capacity::new(None, None, Some(1.0)),
+        };
+        constraints
+            .capacity
+            .add_cargo(pickup(&a), dropoff(&a), pallet());
+        constraints
+            .capacity
+            .add_cargo(pickup(&b), dropoff(&b), pallet());
+        constraints.schedule.add_window(
+            dropoff(&b),
+            Window {
+                earliest: None,
+                latest: Some(5000.0),
+            },
+        );
+
+        let ends = trip(&[1, 2]);
+        let start = vec![
+            ends[0],
+            pickup(&b),
+            dropoff(&b),
+            pickup(&a),
+            dropoff(&a),
+            ends[1],
+        ];
+        assert!(constraints.feasible(&costs, &start));
+
+        // Shorter orders exist, but each of them breaks a constraint.
+        let unconstrained = improve(
+            &costs,
+            &Constraints::default(),
+            Objective::Distance,
+            start.clone(),
+        );
+        assert_ne!(unconstrained, start);
+
+        let improved = improve(&costs, &constraints, Objective::Distance, start.clone());
+        assert_eq!(improved, start);
+    }
+
+    #[test]
+    fn repeated_station_counts_once() {
+        let costs = costs(&[37.0, 38.0, 37.5]);
+        let stops = trip(&[1, 3, 1, 2]);
+        let (load, unload) = visits(&stops, id(1), id(2)).unwrap();
+        assert_eq!((load, unload), (stops[0], stops[3]));
+
+        let mut constraints = Constraints {
+            schedule: Schedule::new(Some(0.0)),
+            capacity: Capacity::new(None, None, Some(1.0)),
+        };
+        constraints.capacity.add_cargo(load, unload, pallet());
+        constraints.schedule.add_window(
+            stops[2],
+            Window {
+                earliest: Some(100_000.0),
+                latest: None,
+            },
+        );
+
+        let pallets: Vec<f64> = constraints
+            .capacity
+            .profile(&stops)
+            .iter()
+            .map(|l| l.pallets)
+            .collect();
+        assert_eq!(pallets, [1.0, 1.0, 1.0, 0.0]);
+        assert!(constraints.feasible(&costs, &stops));
+
+        // Only the second visit waits for its window.
+        let arrivals = constraints.schedule.arrivals_by(&costs, &stops).unwrap();
+        assert!(arrivals[0] < 100_000.0);
+        assert_eq!(arrivals[2], 100_000.0);
+    }
+}
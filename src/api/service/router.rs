@@ -1,4 +1,4 @@
-use axum::routing::{get, post};
+use axum::routing::{get, post, put};
 
 use super::endpoints::*;
 
@@ -13,7 +13,29 @@ pub fn router(state: super::State) -> axum::Router {
             get(get_cargo_request_points),
         )
         .route("/routes/trips/{id}/points", get(get_trip_points))
+        .route("/routes/trips/{id}/latest", get(get_latest_trip))
+        .route("/routes/trips/{id}/history", get(get_trip_history))
+        .route("/routes/trips/{id}/status", put(change_trip_status))
+        .route("/routes/trips/{id}/corridor", get(get_trip_corridor))
+        .route("/routes/stations", get(list_stations))
+        .route("/routes/stations/bulk", post(upsert_stations))
+        .route("/routes/stations/nearest", get(get_nearest_stations))
+        .route(
+            "/routes/stations/{id}",
+            get(get_station).put(put_station).delete(delete_station),
+        )
+        .route(
+            "/routes/cargo_requests/{id}/status",
+            put(change_cargo_request_status),
+        )
         .route("/routes/trips/potential", post(get_potential_routes))
+        .route(
+            "/routes/trips/potential/combined",
+            post(get_combined_routes),
+        )
         .route("/routes/trips/merge", post(merge_routes))
+        .route("/routes/trips/merge/preview", post(preview_merge_routes))
+        .route("/routes/trips/unmerge", post(unmerge_routes))
+        .route("/routes/trips/remove_stations", post(remove_stations))
         .with_state(state)
 }
@@ -0,0 +1,338 @@
+//! Segment acquisition.
+//!
+//! Missing segments are looked up and fetched from the map service without a
+//! transaction open, then stored with [`store`] inside the short transaction
+//! that writes the trip or request they belong to.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::api::map_service;
+use crate::types::Coord;
+
+use super::Error;
+use super::endpoints::Result;
+use super::types::ErrorDetail;
+
+/// Pair of adjacent stations with their coordinates.
+pub type Pair = ((Uuid, Coord), (Uuid, Coord));
+
+/// Route between two adjacent stations, as stored in the `segment` table.
+pub struct Segment {
+    pub s1: Uuid,
+    pub s2: Uuid,
+    pub points: Vec<Coord>,
+    pub distance: i32,
+    pub time: i32,
+}
+
+impl Segment {
+    fn new(
+        s1: Uuid,
+        s2: Uuid,
+        route: map_service::CreateRouteResponse,
+        axis_order: map_service::AxisOrder,
+    ) -> Self {
+        Self {
+            s1,
+            s2,
+            points: route
+                .way
+                .into_iter()
+                .map(|point| axis_order.decode(point))
+                .collect(),
+            distance: route.distance as i32,
+            time: route.duration as i32,
+        }
+    }
+}
+
+/// Fetches the segments missing between adjacent `stations`, ready to be [`store`]d.
+///
+/// If some of them cannot be fetched, the ones that were are cached right
+/// away, so a retry only asks the map service for the failed ones.
+pub async fn acquire(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    stations: &[(Uuid, Coord)],
+) -> Result<Vec<Segment>> {
+    let pairs = missing(pool, stations).await?;
+
+    if pairs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    if client.config().multi_stop && stations.len() > 2 {
+        match fetch_whole(client, stations, &pairs).await {
+            Ok(segments) => return Ok(segments),
+            Err(e) => log::warn!(
+                "multi-stop route through {} stations failed ({e}), fetching segments one by one",
+                stations.len()
+            ),
+        }
+    }
+
+    let (segments, failures) = fetch(client, pairs).await;
+
+    if failures.is_empty() {
+        return Ok(segments);
+    }
+
+    let mut conn = pool.acquire().await?;
+    store(&mut conn, &segments).await?;
+
+    Err(Error::SegmentsUnavailable(failures))
+}
+
+/// Fetches and stores the segments missing between adjacent `stations`, so
+/// that routes can be read even if their segments were dropped meanwhile.
+pub async fn ensure(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    stations: &[(Uuid, Coord)],
+) -> Result<()> {
+    let fetched = acquire(pool, client, stations).await?;
+
+    if !fetched.is_empty() {
+        let mut conn = pool.acquire().await?;
+        store(&mut conn, &fetched).await?;
+    }
+
+    Ok(())
+}
+
+/// Fetches the given pairs from the map service and caches them right away.
+/// Pairs that cannot be fetched are logged and left out, unless every one of
+/// them is `required`: then the call fails once the others are cached.
+pub async fn cache(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    pairs: Vec<Pair>,
+    required: bool,
+) -> Result<Vec<Segment>> {
+    let (segments, failures) = fetch(client, pairs).await;
+
+    let mut conn = pool.acquire().await?;
+    store(&mut conn, &segments).await?;
+
+    if required && !failures.is_empty() {
+        return Err(Error::SegmentsUnavailable(failures));
+    }
+
+    for failure in &failures {
+        log::warn!(
+            "cannot fetch segment {}: {}",
+            failure.target,
+            failure.message
+        );
+    }
+
+    Ok(segments)
+}
+
+/// Returns distance and time of every cached segment between two of the given stations.
+pub async fn between(
+    pool: &sqlx::PgPool,
+    stations: &[Uuid],
+) -> Result<Vec<(Uuid, Uuid, i32, i32)>> {
+    let segments = sqlx::query_as(
+        "SELECT s1, s2, distance, time
+        FROM segment
+        WHERE s1 = ANY($1) AND s2 = ANY($1);",
+    )
+    .bind(stations)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(segments)
+}
+
+/// Returns the cached segments between adjacent `stations`, in order.
+/// Fails if any of them is not cached.
+pub async fn along(pool: &sqlx::PgPool, stations: &[Uuid]) -> Result<Vec<Segment>> {
+    let (s1, s2): (Vec<Uuid>, Vec<Uuid>) =
+        stations.windows(2).map(|pair| (pair[0], pair[1])).unzip();
+
+    let rows: Vec<(Uuid, Uuid, Vec<Coord>, i32, i32)> = sqlx::query_as(
+        "SELECT seg.s1, seg.s2, seg.points, seg.distance, seg.time
+        FROM unnest($1::uuid[], $2::uuid[]) WITH ORDINALITY AS pairs(s1, s2, idx)
+        INNER JOIN segment seg ON seg.s1 = pairs.s1 AND seg.s2 = pairs.s2
+        ORDER BY pairs.idx;",
+    )
+    .bind(&s1)
+    .bind(&s2)
+    .fetch_all(pool)
+    .await?;
+
+    if let Some((s1, s2)) = s1
+        .into_iter()
+        .zip(s2)
+        .find(|pair| !rows.iter().any(|row| (row.0, row.1) == *pair))
+    {
+        return Err(Error::NotFound(format!("no segment between {s1} and {s2}")));
+    }
+
+    let segments = rows
+        .into_iter()
+        .map(|(s1, s2, points, distance, time)| Segment {
+            s1,
+            s2,
+            points,
+            distance,
+            time,
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+/// Returns the pairs of adjacent stations that have no cached segment yet.
+/// Every pair is returned once, even if it occurs several times in `stations`,
+/// and a station next to itself needs no segment.
+pub async fn missing(pool: &sqlx::PgPool, stations: &[(Uuid, Coord)]) -> Result<Vec<Pair>> {
+    let (s1, s2): (Vec<Uuid>, Vec<Uuid>) = stations
+        .windows(2)
+        .map(|pair| (pair[0].0, pair[1].0))
+        .unzip();
+
+    let existing: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        "SELECT seg.s1, seg.s2
+        FROM segment seg
+        INNER JOIN unnest($1::uuid[], $2::uuid[]) AS pairs(s1, s2)
+            ON seg.s1 = pairs.s1 AND seg.s2 = pairs.s2;",
+    )
+    .bind(&s1)
+    .bind(&s2)
+    .fetch_all(pool)
+    .await?;
+
+    let mut seen: HashSet<(Uuid, Uuid)> = existing.into_iter().collect();
+
+    let missing = stations
+        .windows(2)
+        .filter(|pair| pair[0].0 != pair[1].0 && seen.insert((pair[0].0, pair[1].0)))
+        .map(|pair| (pair[0], pair[1]))
+        .collect();
+
+    Ok(missing)
+}
+
+/// Fetches routes for the given station pairs from the map service, at most
+/// [`map_service::ClientConfig::concurrency`] at a time.
+/// Returns the fetched segments and a failure for every pair that could not be fetched.
+pub async fn fetch(
+    client: &map_service::Client,
+    pairs: Vec<Pair>,
+) -> (Vec<Segment>, Vec<ErrorDetail>) {
+    let permits = Arc::new(Semaphore::new(client.config().concurrency.max(1)));
+    let axis_order = client.config().axis_order;
+    let mut tasks = JoinSet::new();
+    // Pairs by task, to name the pair of a task that panicked or was cancelled.
+    let mut task_pairs = HashMap::new();
+
+    for ((s1, p1), (s2, p2)) in pairs {
+        let client = client.clone();
+        let permits = permits.clone();
+
+        let task = tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+
+            let route = client
+                .create_route(map_service::CreateRouteRequest {
+                    stops: vec![axis_order.encode(p1), axis_order.encode(p2)],
+                })
+                .await
+                .map_err(|e| ErrorDetail {
+                    target: format!("{s1}->{s2}"),
+                    message: e.to_string(),
+                })?;
+
+            Ok::<_, ErrorDetail>(Segment::new(s1, s2, route, axis_order))
+        });
+        task_pairs.insert(task.id(), (s1, s2));
+    }
+
+    let mut segments = Vec::new();
+    let mut failures = Vec::new();
+
+    while let Some(result) = tasks.join_next_with_id().await {
+        match result {
+            Ok((_, Ok(segment))) => segments.push(segment),
+            Ok((_, Err(failure))) => failures.push(failure),
+            Err(e) => {
+                let (s1, s2) = task_pairs[&e.id()];
+                failures.push(ErrorDetail {
+                    target: format!("{s1}->{s2}"),
+                    message: format!("fetch task failed: {e}"),
+                });
+            }
+        }
+    }
+
+    (segments, failures)
+}
+
+/// Fetches the route through all `stations` in one map service call and
+/// splits it into the segments for the missing `pairs`.
+async fn fetch_whole(
+    client: &map_service::Client,
+    stations: &[(Uuid, Coord)],
+    pairs: &[Pair],
+) -> std::result::Result<Vec<Segment>, map_service::Error> {
+    let axis_order = client.config().axis_order;
+    let stops: Vec<[f64; 2]> = stations
+        .iter()
+        .map(|(_, p)| axis_order.encode(*p))
+        .collect();
+
+    let route = client
+        .create_multi_route(map_service::CreateRouteRequest {
+            stops: stops.clone(),
+        })
+        .await?;
+
+    let legs_count = route.legs.len();
+    let legs = route.into_legs(&stops).ok_or_else(|| {
+        map_service::Error::Body(format!(
+            "expected {} legs for {} stops, got {legs_count}",
+            stops.len() - 1,
+            stops.len()
+        ))
+    })?;
+
+    let mut wanted: HashSet<(Uuid, Uuid)> =
+        pairs.iter().map(|((s1, _), (s2, _))| (*s1, *s2)).collect();
+
+    let segments = stations
+        .windows(2)
+        .zip(legs)
+        .filter(|(pair, _)| wanted.remove(&(pair[0].0, pair[1].0)))
+        .map(|(pair, leg)| Segment::new(pair[0].0, pair[1].0, leg, axis_order))
+        .collect();
+
+    Ok(segments)
+}
+
+/// Upserts fetched segments. Segments stored concurrently by another request are kept.
+pub async fn store(tx: &mut sqlx::PgConnection, segments: &[Segment]) -> Result<()> {
+    for segment in segments {
+        sqlx::query(
+            "INSERT INTO segment (s1, s2, points, distance, time)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (s1, s2) DO NOTHING;",
+        )
+        .bind(segment.s1)
+        .bind(segment.s2)
+        .bind(&segment.points)
+        .bind(segment.distance)
+        .bind(segment.time)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! Spatial lookups over stations and trips.
+//!
+//! With [`Storage::PostGis`] they run inside Postgres on the `geography`
+//! columns added by [`schema::enable_postgis`]. With [`Storage::Plain`] the
+//! candidates are loaded and measured with [`planner::distance`]. These two
+//! lookups are all that PostGIS is used for; every other query reads the
+//! `POINT` columns in both modes.
+
+use uuid::Uuid;
+
+use crate::schema;
+use crate::types::Coord;
+
+use super::Error;
+use super::endpoints::Result;
+use super::planner;
+
+/// How the spatial lookups of this module run.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Storage {
+    /// `POINT` and `POINT[]` columns only.
+    #[default]
+    Plain,
+    /// `geography` columns with GiST indexes.
+    PostGis,
+}
+
+impl std::str::FromStr for Storage {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Storage::Plain),
+            "postgis" => Ok(Storage::PostGis),
+            _ => Err(format!("unknown storage {s}, expected plain or postgis")),
+        }
+    }
+}
+
+/// Prepares the database for `storage`. PostGIS storage gets its geography
+/// columns and indexes added, which fails if postgis is not installed.
+pub async fn prepare(pool: &sqlx::PgPool, storage: Storage) -> anyhow::Result<()> {
+    match storage {
+        Storage::PostGis => schema::enable_postgis(pool).await,
+        Storage::Plain => Ok(()),
+    }
+}
+
+/// Returns up to `limit` stations closest to `point` with their distance in
+/// meters, closest first.
+pub async fn nearest_stations(
+    pool: &sqlx::PgPool,
+    storage: Storage,
+    point: Coord,
+    limit: i64,
+) -> Result<Vec<(Uuid, String, Coord, f64)>> {
+    match storage {
+        Storage::PostGis => {
+            let stations = sqlx::query_as(
+                "SELECT id, address, coords,
+                    ST_Distance(location, ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography)
+                FROM station
+                ORDER BY location <-> ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography
+                LIMIT $3;",
+            )
+            .bind(point.lon)
+            .bind(point.lat)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(stations)
+        }
+        Storage::Plain => {
+            let stations: Vec<(Uuid, String, Coord)> =
+                sqlx::query_as("SELECT id, address, coords FROM station;")
+                    .fetch_all(pool)
+                    .await?;
+
+            let mut stations: Vec<(Uuid, String, Coord, f64)> = stations
+                .into_iter()
+                .map(|(id, address, coords)| {
+                    (id, address, coords, planner::distance(&coords, &point))
+                })
+                .collect();
+
+            stations.sort_by(|a, b| a.3.total_cmp(&b.3));
+            stations.truncate(limit.max(0) as usize);
+
+            Ok(stations)
+        }
+    }
+}
+
+/// Returns the open cargo requests not assigned to any trip whose pickup and
+/// drop-off both lie within `width` meters of the road `trip` takes.
+///
+/// Without PostGIS the distance to each leg of the road is measured on a flat
+/// projection around the station, which is exact enough at corridor widths.
+pub async fn requests_in_corridor(
+    pool: &sqlx::PgPool,
+    storage: Storage,
+    trip: &Uuid,
+    width: f64,
+) -> Result<Vec<Uuid>> {
+    let exists: Option<i32> = sqlx::query_scalar("SELECT 1 FROM trip WHERE id = $1;")
+        .bind(trip)
+        .fetch_optional(pool)
+        .await?;
+
+    if exists.is_none() {
+        return Err(Error::NotFound(format!(
+            "cannot find trip with id {}",
+            trip
+        )));
+    }
+
+    match storage {
+        Storage::PostGis => {
+            let requests = sqlx::query_scalar(
+                "WITH road AS (
+                    SELECT seg.line
+                    FROM path p1
+                    INNER JOIN path p2 ON p1.trip_id = p2.trip_id AND p2.index = p1.index + 1
+                    INNER JOIN segment seg ON seg.s1 = p1.station_id AND seg.s2 = p2.station_id
+                    WHERE p1.trip_id = $1 AND seg.line IS NOT NULL
+                )
+                SELECT r.id
+                FROM request r
+                INNER JOIN station s_src ON r.source = s_src.id
+                INNER JOIN station s_dst ON r.destination = s_dst.id
+                WHERE r.trip_id IS NULL
+                    AND r.status NOT IN ('completed', 'cancelled')
+                    AND EXISTS (SELECT 1 FROM road WHERE ST_DWithin(road.line, s_src.location, $2))
+                    AND EXISTS (SELECT 1 FROM road WHERE ST_DWithin(road.line, s_dst.location, $2))
+                ORDER BY r.id;",
+            )
+            .bind(trip)
+            .bind(width)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(requests)
+        }
+        Storage::Plain => {
+            let road: Vec<Vec<Coord>> = sqlx::query_scalar(
+                "SELECT seg.points
+                FROM path p1
+                INNER JOIN path p2 ON p1.trip_id = p2.trip_id AND p2.index = p1.index + 1
+                INNER JOIN segment seg ON seg.s1 = p1.station_id AND seg.s2 = p2.station_id
+                WHERE p1.trip_id = $1;",
+            )
+            .bind(trip)
+            .fetch_all(pool)
+            .await?;
+
+            let requests: Vec<(Uuid, Coord, Coord)> = sqlx::query_as(
+                "SELECT r.id, s_src.coords, s_dst.coords
+                FROM request r
+                INNER JOIN station s_src ON r.source = s_src.id
+                INNER JOIN station s_dst ON r.destination = s_dst.id
+                WHERE r.trip_id IS NULL
+                    AND r.status NOT IN ('completed', 'cancelled')
+                ORDER BY r.id;",
+            )
+            .fetch_all(pool)
+            .await?;
+
+            let near = |point: &Coord| {
+                road.iter().any(|points| match points.as_slice() {
+                    [p] => planner::distance(p, point) <= width,
+                    points => points
+                        .windows(2)
+                        .any(|leg| distance_to_leg(point, &leg[0], &leg[1]) <= width),
+                })
+            };
+
+            Ok(requests
+                .into_iter()
+                .filter(|(_, source, destination)| near(source) && near(destination))
+                .map(|(id, ..)| id)
+                .collect())
+        }
+    }
+}
+
+/// Distance in meters from `point` to the straight leg between `a` and `b`.
+fn distance_to_leg(point: &Coord, a: &Coord, b: &Coord) -> f64 {
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+
+    let scale = point.lat.to_radians().cos();
+    let project = |c: &Coord| {
+        (
+            (c.lon - point.lon) * scale * METERS_PER_DEGREE,
+            (c.lat - point.lat) * METERS_PER_DEGREE,
+        )
+    };
+
+    let (ax, ay) = project(a);
+    let (bx, by) = project(b);
+    let (dx, dy) = (bx - ax, by - ay);
+    let length = dx * dx + dy * dy;
+
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (-(ax * dx + ay * dy) / length).clamp(0.0, 1.0)
+    };
+
+    (ax + t * dx).hypot(ay + t * dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= expected * 0.01,
+            "{actual} is not within 1% of {expected}"
+        );
+    }
+
+    #[test]
+    fn beyond_the_leg_measures_to_its_end() {
+        let (a, b) = (Coord::new(55.0, 37.0), Coord::new(55.0, 37.01));
+        let point = Coord::new(55.0, 37.02);
+
+        assert_near(
+            distance_to_leg(&point, &a, &b),
+            planner::distance(&point, &b),
+        );
+        assert_near(
+            distance_to_leg(&point, &b, &a),
+            planner::distance(&point, &b),
+        );
+    }
+
+    #[test]
+    fn beside_the_leg_measures_straight_across() {
+        let (a, b) = (Coord::new(55.0, 37.0), Coord::new(55.0, 37.01));
+        let point = Coord::new(55.001, 37.005);
+
+        assert_near(
+            distance_to_leg(&point, &a, &b),
+            planner::distance(&point, &Coord::new(55.0, 37.005)),
+        );
+    }
+
+    #[test]
+    fn zero_length_leg_is_a_point() {
+        let a = Coord::new(55.0, 37.0);
+        let point = Coord::new(55.001, 37.001);
+
+        assert_near(
+            distance_to_leg(&point, &a, &a),
+            planner::distance(&point, &a),
+        );
+        assert_eq!(distance_to_leg(&a, &a, &a), 0.0);
+    }
+}
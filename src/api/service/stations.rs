@@ -0,0 +1,395 @@
+//! Writing stations that may already be stored.
+//!
+//! Clients pick station IDs, so the same ID can arrive with an address or
+//! coordinates different from the stored row. [`resolve`] compares the two
+//! and applies the request's [`OnConflict`] policy. When a station moves to
+//! other coordinates, [`write`] deletes every cached segment touching it, and
+//! the ones trips and cargo requests use are [`refetch`]ed beforehand, to be
+//! stored in the same transaction.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::map_service;
+use crate::types::Coord;
+
+use super::Error;
+use super::endpoints::Result;
+use super::segments::{self, Pair, Segment};
+use super::types::{ErrorDetail, Station};
+
+/// What to do when a station ID is already stored with other data.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Fail the request.
+    Reject,
+    /// Store the new data and fetch again the segments touching the station.
+    Update,
+    /// Keep the stored data and report a warning.
+    #[default]
+    Keep,
+}
+
+/// A station as it will be written, with the row stored before the request.
+pub struct Resolved {
+    pub station: Station,
+    pub stored: Option<Station>,
+}
+
+impl Resolved {
+    /// Whether the stored row is overwritten.
+    pub fn is_update(&self) -> bool {
+        self.stored
+            .as_ref()
+            .is_some_and(|stored| *stored != self.station)
+    }
+
+    /// Whether the stored row is overwritten with other coordinates, which
+    /// makes the cached segments touching it stale.
+    pub fn moves(&self) -> bool {
+        self.stored
+            .as_ref()
+            .is_some_and(|stored| stored.coords != self.station.coords)
+    }
+}
+
+/// Returns the stored stations among `ids`.
+pub async fn get(pool: &sqlx::PgPool, ids: &[Uuid]) -> Result<HashMap<Uuid, Station>> {
+    let stations: Vec<(Uuid, String, Coord)> =
+        sqlx::query_as("SELECT id, address, coords FROM station WHERE id = ANY($1);")
+            .bind(ids)
+            .fetch_all(pool)
+            .await?;
+
+    Ok(stations
+        .into_iter()
+        .map(|(id, address, coords)| {
+            (
+                id,
+                Station {
+                    id,
+                    address,
+                    coords,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Names the fields in which `stored` and `incoming` differ.
+fn differences(stored: &Station, incoming: &Station) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+
+    if stored.address != incoming.address {
+        fields.push("address");
+    }
+
+    if stored.coords != incoming.coords {
+        fields.push("coords");
+    }
+
+    fields
+}
+
+/// Compares the `incoming` stations, keyed by their JSON path, with the stored
+/// ones and decides what to write following `policy`. Returns the stations in
+/// order and a warning for every one whose stored data was kept.
+pub async fn resolve(
+    pool: &sqlx::PgPool,
+    incoming: &[(&str, &Station)],
+    policy: OnConflict,
+) -> Result<(Vec<Resolved>, Vec<ErrorDetail>)> {
+    let ids: Vec<Uuid> = incoming.iter().map(|(_, station)| station.id).collect();
+    let mut stored = get(pool, &ids).await?;
+
+    let mut resolved = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (target, station) in incoming {
+        let stored = stored.remove(&station.id);
+        let fields = stored
+            .as_ref()
+            .map(|stored| differences(stored, station))
+            .unwrap_or_default();
+
+        let station = match (stored.as_ref(), policy) {
+            (Some(stored), OnConflict::Reject | OnConflict::Keep) if !fields.is_empty() => {
+                let message = format!(
+                    "station {} is stored with a different {}: {:?} at ({}, {})",
+                    station.id,
+                    fields.join(" and "),
+                    stored.address,
+                    stored.coords.lat,
+                    stored.coords.lon
+                );
+
+                if policy == OnConflict::Reject {
+                    conflicts.push(format!("{target}: {message}"));
+                } else {
+                    warnings.push(ErrorDetail {
+                        target: target.to_string(),
+                        message: format!("{message}, the stored data is kept"),
+                    });
+                }
+
+                stored.clone()
+            }
+            _ => (*station).clone(),
+        };
+
+        resolved.push(Resolved { station, stored });
+    }
+
+    if !conflicts.is_empty() {
+        return Err(Error::Conflict(format!(
+            "{}. Send onStationConflict \"update\" or \"keep\" to override",
+            conflicts.join("; ")
+        )));
+    }
+
+    Ok((resolved, warnings))
+}
+
+/// Returns the pairs of adjacent stations on trip paths and cargo requests
+/// that go through one of the `stations`, with their stored coordinates.
+async fn used_pairs<'c, E>(executor: E, stations: &[Uuid]) -> Result<Vec<Pair>>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let rows: Vec<(Uuid, Coord, Uuid, Coord)> = sqlx::query_as(
+        "SELECT a.id, a.coords, b.id, b.coords
+        FROM (
+            SELECT p1.station_id AS s1, p2.station_id AS s2
+            FROM path p1
+            INNER JOIN path p2 ON p1.trip_id = p2.trip_id AND p2.index = p1.index + 1
+            WHERE p1.station_id = ANY($1) OR p2.station_id = ANY($1)
+            UNION
+            SELECT source, destination
+            FROM request
+            WHERE source = ANY($1) OR destination = ANY($1)
+        ) pairs
+        INNER JOIN station a ON a.id = pairs.s1
+        INNER JOIN station b ON b.id = pairs.s2
+        WHERE pairs.s1 <> pairs.s2;",
+    )
+    .bind(stations)
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(s1, p1, s2, p2)| ((s1, p1), (s2, p2)))
+        .collect())
+}
+
+/// Fetches with the new coordinates every segment that trips and cargo
+/// requests use next to the stations `resolved` moves. Fails if any of them
+/// cannot be fetched, as the stations must not move without them.
+pub async fn refetch(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    resolved: &[Resolved],
+) -> Result<Vec<Segment>> {
+    let moved: HashMap<Uuid, Coord> = resolved
+        .iter()
+        .filter(|resolved| resolved.moves())
+        .map(|resolved| (resolved.station.id, resolved.station.coords))
+        .collect();
+
+    if moved.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<Uuid> = moved.keys().copied().collect();
+    let moved_to = |(id, coords): (Uuid, Coord)| (id, moved.get(&id).copied().unwrap_or(coords));
+
+    let pairs = used_pairs(pool, &ids)
+        .await?
+        .into_iter()
+        .map(|(a, b)| (moved_to(a), moved_to(b)))
+        .collect();
+
+    let (fetched, failures) = segments::fetch(client, pairs).await;
+
+    if !failures.is_empty() {
+        return Err(Error::SegmentsUnavailable(failures));
+    }
+
+    Ok(fetched)
+}
+
+/// Checks inside the transaction moving the `resolved` stations that every
+/// segment used next to them was refetched, failing with a conflict if a trip
+/// or cargo request started using one of them meanwhile.
+pub async fn check_refetched(
+    tx: &mut sqlx::PgConnection,
+    resolved: &[Resolved],
+    fetched: &[Segment],
+) -> Result<()> {
+    let moved: Vec<Uuid> = resolved
+        .iter()
+        .filter(|resolved| resolved.moves())
+        .map(|resolved| resolved.station.id)
+        .collect();
+
+    if moved.is_empty() {
+        return Ok(());
+    }
+
+    let fetched: HashSet<(Uuid, Uuid)> = fetched.iter().map(|seg| (seg.s1, seg.s2)).collect();
+
+    for ((s1, _), (s2, _)) in used_pairs(&mut *tx, &moved).await? {
+        if !fetched.contains(&(s1, s2)) {
+            return Err(Error::Conflict(format!(
+                "segment {s1}->{s2} started being used while its stations moved, retry the request"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a resolved station, checking the stored row did not change since it
+/// was resolved. A moved station loses every cached segment touching it.
+pub async fn write(tx: &mut sqlx::PgConnection, resolved: &Resolved) -> Result<()> {
+    let station = &resolved.station;
+
+    let current: Option<(String, Coord)> =
+        sqlx::query_as("SELECT address, coords FROM station WHERE id = $1 FOR UPDATE;")
+            .bind(station.id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    let unchanged = match (&current, &resolved.stored) {
+        (None, None) => true,
+        (Some((address, coords)), Some(stored)) => {
+            *address == stored.address && *coords == stored.coords
+        }
+        _ => false,
+    };
+
+    if !unchanged {
+        return Err(Error::Conflict(format!(
+            "station {} was changed concurrently, retry the request",
+            station.id
+        )));
+    }
+
+    if current.is_none() {
+        sqlx::query(
+            "INSERT INTO station (id, address, coords)
+            VALUES ($1, $2, $3);",
+        )
+        .bind(station.id)
+        .bind(&station.address)
+        .bind(station.coords)
+        .execute(&mut *tx)
+        .await?;
+    } else if resolved.is_update() {
+        sqlx::query("UPDATE station SET address = $2, coords = $3 WHERE id = $1;")
+            .bind(station.id)
+            .bind(&station.address)
+            .bind(station.coords)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if resolved.moves() {
+        sqlx::query("DELETE FROM segment WHERE s1 = $1 OR s2 = $1;")
+            .bind(station.id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Creates the `incoming` stations that are missing and overwrites the stored
+/// ones, keyed by their JSON path, in one transaction. The segments used next
+/// to the stations that move are fetched again and stored with them.
+pub async fn upsert(
+    pool: &sqlx::PgPool,
+    client: &map_service::Client,
+    incoming: &[(&str, &Station)],
+) -> Result<Vec<Resolved>> {
+    let (resolved, _) = resolve(pool, incoming, OnConflict::Update).await?;
+    let fetched = refetch(pool, client, &resolved).await?;
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    for station in &resolved {
+        write(&mut tx, station).await?;
+    }
+
+    check_refetched(&mut tx, &resolved, &fetched).await?;
+    segments::store(&mut tx, &fetched).await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| Error::Database(format!("error commiting transaction: {e}")))?;
+
+    Ok(resolved)
+}
+
+/// Deletes a station with its cached segments. Refused while a trip or cargo
+/// request still goes through it.
+pub async fn delete(pool: &sqlx::PgPool, id: &Uuid) -> Result<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| Error::Database(format!("error starting transaction: {e}")))?;
+
+    let exists: Option<i32> = sqlx::query_scalar("SELECT 1 FROM station WHERE id = $1 FOR UPDATE;")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    if exists.is_none() {
+        return Err(Error::NotFound(format!(
+            "cannot find station with id {}",
+            id
+        )));
+    }
+
+    let (trips, requests): (i64, i64) = sqlx::query_as(
+        "SELECT
+            (SELECT count(DISTINCT t.id)
+                FROM trip t
+                LEFT JOIN path p ON p.trip_id = t.id
+                WHERE p.station_id = $1 OR t.source = $1 OR t.destination = $1),
+            (SELECT count(*) FROM request WHERE source = $1 OR destination = $1);",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if trips > 0 || requests > 0 {
+        return Err(Error::Conflict(format!(
+            "station {} is used by {} trip(s) and {} cargo request(s)",
+            id, trips, requests
+        )));
+    }
+
+    sqlx::query("DELETE FROM segment WHERE s1 = $1 OR s2 = $1;")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM station WHERE id = $1;")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| Error::Database(format!("error commiting transaction: {e}")))?;
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Lifecycle status of trips and cargo requests.
+
+use serde::{Deserialize, Serialize};
+
+/// Where a trip or cargo request is in its lifecycle. A completed cargo
+/// request is one that was delivered.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Draft,
+    Planned,
+    InProgress,
+    Completed,
+    Cancelled,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Draft => "draft",
+            Status::Planned => "planned",
+            Status::InProgress => "in_progress",
+            Status::Completed => "completed",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    /// Completed and cancelled trips and requests never change again.
+    pub fn is_closed(self) -> bool {
+        matches!(self, Status::Completed | Status::Cancelled)
+    }
+
+    /// Whether the status may change to `next`. Staying the same is allowed.
+    pub fn can_become(self, next: Status) -> bool {
+        use Status::*;
+
+        self == next
+            || matches!(
+                (self, next),
+                (Draft, Planned | Cancelled)
+                    | (Planned, Draft | InProgress | Cancelled)
+                    | (InProgress, Completed | Cancelled)
+            )
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(Status::Draft),
+            "planned" => Ok(Status::Planned),
+            "in_progress" => Ok(Status::InProgress),
+            "completed" => Ok(Status::Completed),
+            "cancelled" => Ok(Status::Cancelled),
+            _ => Err(format!("unknown status {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Status::{self, *};
+
+    #[test]
+    fn transitions() {
+        let all = [Draft, Planned, InProgress, Completed, Cancelled];
+        let allowed: &[(Status, &[Status])] = &[
+            (Draft, &[Draft, Planned, Cancelled]),
+            (Planned, &[Draft, Planned, InProgress, Cancelled]),
+            (InProgress, &[InProgress, Completed, Cancelled]),
+            (Completed, &[Completed]),
+            (Cancelled, &[Cancelled]),
+        ];
+
+        for (from, to) in allowed {
+            for next in all {
+                assert_eq!(
+                    from.can_become(next),
+                    to.contains(&next),
+                    "{} -> {}",
+                    from.as_str(),
+                    next.as_str()
+                );
+            }
+        }
+    }
+}
@@ -1,16 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
-pub struct Coords {
-    pub lat: f64,
-    pub lon: f64,
-}
+pub use super::planner::{Metric, Objective};
+pub use super::stations::OnConflict;
+pub use super::status::Status;
+pub use crate::types::Coord;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Station {
     pub id: uuid::Uuid,
     pub address: String,
-    pub coords: Coords,
+    pub coords: Coord,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -23,6 +22,121 @@ pub struct GetStationResponse {
     pub station: Station,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PutStationRequest {
+    pub address: String,
+    pub coords: Coord,
+}
+
+fn default_list_limit() -> i64 {
+    50
+}
+
+/// Filters of the station list. The bounding box is given by all four
+/// corners or not at all.
+#[derive(Serialize, Deserialize)]
+pub struct ListStationsRequest {
+    #[serde(default, rename = "minLat")]
+    pub min_lat: Option<f64>,
+
+    #[serde(default, rename = "minLon")]
+    pub min_lon: Option<f64>,
+
+    #[serde(default, rename = "maxLat")]
+    pub max_lat: Option<f64>,
+
+    #[serde(default, rename = "maxLon")]
+    pub max_lon: Option<f64>,
+
+    /// Part of the address, matched case-insensitively.
+    #[serde(default)]
+    pub q: Option<String>,
+
+    #[serde(default = "default_list_limit")]
+    pub limit: i64,
+
+    #[serde(default)]
+    pub offset: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListStationsResponse {
+    /// Ordered by address.
+    pub stations: Vec<Station>,
+
+    /// Stations matching the filters on all pages.
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpsertStationsRequest {
+    pub stations: Vec<Station>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpsertStationsResponse {
+    pub created: Vec<uuid::Uuid>,
+    pub updated: Vec<uuid::Uuid>,
+    pub unchanged: Vec<uuid::Uuid>,
+}
+
+/// Time a station may be served in, in seconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct TimeWindow {
+    pub earliest: Option<i64>,
+    pub latest: Option<i64>,
+}
+
+/// Cargo of a cargo request, or the load on board a trip.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Cargo {
+    /// In kilograms.
+    #[serde(default)]
+    pub weight: f64,
+
+    /// In cubic meters.
+    #[serde(default)]
+    pub volume: f64,
+
+    #[serde(default)]
+    pub pallets: i32,
+}
+
+/// What a trip's vehicle can carry. A missing value means unlimited.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Capacity {
+    pub weight: Option<f64>,
+    pub volume: Option<f64>,
+    pub pallets: Option<i32>,
+}
+
+fn default_nearest_limit() -> i64 {
+    10
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetNearestStationsRequest {
+    pub lat: f64,
+    pub lon: f64,
+
+    #[serde(default = "default_nearest_limit")]
+    pub limit: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NearestStation {
+    pub station: Station,
+
+    /// In meters.
+    pub distance: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetNearestStationsResponse {
+    /// Closest first.
+    pub stations: Vec<NearestStation>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CreateRouteRequest {
     #[serde(rename = "fromStation")]
@@ -30,11 +144,39 @@ pub struct CreateRouteRequest {
 
     #[serde(rename = "toStation")]
     pub to_station: Station,
+
+    /// Pickup window of a cargo request.
+    #[serde(default, rename = "fromWindow")]
+    pub from_window: Option<TimeWindow>,
+
+    /// Delivery window of a cargo request.
+    #[serde(default, rename = "toWindow")]
+    pub to_window: Option<TimeWindow>,
+
+    /// Departure of a trip from its first station, in seconds since the Unix epoch.
+    #[serde(default, rename = "departureTime")]
+    pub departure: Option<i64>,
+
+    /// Cargo of a cargo request.
+    #[serde(default)]
+    pub cargo: Option<Cargo>,
+
+    /// Capacity of a trip's vehicle.
+    #[serde(default)]
+    pub capacity: Option<Capacity>,
+
+    /// What to do if a station ID is already stored with other data.
+    #[serde(default, rename = "onStationConflict")]
+    pub on_station_conflict: OnConflict,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateRouteResponse {
     pub id: uuid::Uuid,
+
+    /// Stations whose stored data was kept instead of the data sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ErrorDetail>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -49,6 +191,14 @@ pub struct Waypoint {
 
     #[serde(rename = "tripTime")]
     pub trip_time: u64,
+
+    /// When the station is served, if the trip has a departure time.
+    #[serde(rename = "arrivalTime", skip_serializing_if = "Option::is_none")]
+    pub arrival: Option<i64>,
+
+    /// Load on board when leaving the station, for trips.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load: Option<Cargo>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -73,10 +223,144 @@ pub struct GetPotentialRoutesRequest {
 
     #[serde(rename = "cargoRequestRouteIds")]
     pub cargo_requests: Vec<uuid::Uuid>,
+
+    #[serde(flatten)]
+    pub options: ScoringOptions,
+}
+
+/// How candidates are scored and which detours are acceptable.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ScoringOptions {
+    /// Road costs are used anyway when a stop has a time window.
+    #[serde(default)]
+    pub metric: ScoringMetric,
+
+    /// Fetch segments missing for road scoring from the map service instead
+    /// of falling back to great-circle distance.
+    #[serde(default, rename = "fetchMissingSegments")]
+    pub fetch_missing: bool,
+
+    /// Overrides the service-wide default, in meters.
+    #[serde(rename = "maxAddedDistance")]
+    pub max_added_distance: Option<f64>,
+
+    /// Overrides the service-wide default, in seconds.
+    #[serde(rename = "maxAddedTime")]
+    pub max_added_time: Option<f64>,
+
+    /// Overrides the service-wide default, in percent of the trip distance.
+    #[serde(rename = "maxDetourPercent")]
+    pub max_detour_percent: Option<f64>,
+
+    pub objective: Option<Objective>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoringMetric {
+    #[default]
+    Haversine,
+    Road,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PotentialRoute {
+    #[serde(rename = "routeId")]
+    pub route: uuid::Uuid,
+
+    pub metric: Metric,
+
+    #[serde(rename = "addedDistance")]
+    pub added_distance: u64,
+
+    #[serde(rename = "addedTime")]
+    pub added_time: u64,
+
+    /// Position of the pickup in `stationIds`.
+    #[serde(rename = "pickupIndex")]
+    pub pickup_index: usize,
+
+    /// Position of the drop-off in `stationIds`.
+    #[serde(rename = "dropoffIndex")]
+    pub dropoff_index: usize,
+
+    /// Station order of the trip with the cargo request inserted.
+    #[serde(rename = "stationIds")]
+    pub stations: Vec<uuid::Uuid>,
+
+    /// When every station in `stationIds` is served, if the trip has a departure time.
+    #[serde(rename = "arrivalTimes", skip_serializing_if = "Vec::is_empty")]
+    pub arrivals: Vec<i64>,
+
+    #[serde(rename = "withinLimits")]
+    pub within_limits: bool,
+
+    /// Whether every station is served within its time window. If not, or
+    /// not within capacity, the cheapest insertion ignoring both is shown.
+    #[serde(rename = "withinTimeWindows")]
+    pub within_time_windows: bool,
+
+    /// Whether the load stays within the trip's capacity all along.
+    #[serde(rename = "withinCapacity")]
+    pub within_capacity: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct GetPotentialRoutesResponse {
+    /// Candidates within the detour limits, time windows and capacity, best first.
+    #[serde(rename = "routeIds")]
+    pub requests: Vec<uuid::Uuid>,
+
+    /// All candidates, best first.
+    pub candidates: Vec<PotentialRoute>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCombinedRoutesRequest {
+    #[serde(rename = "tripRouteId")]
+    pub trip: uuid::Uuid,
+
+    #[serde(rename = "cargoRequestRouteIds")]
+    pub cargo_requests: Vec<uuid::Uuid>,
+
+    /// Limits apply to the detour of all chosen cargo requests together.
+    #[serde(flatten)]
+    pub options: ScoringOptions,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCombinedRoutesResponse {
+    /// Chosen cargo requests, in the order they were picked.
+    #[serde(rename = "routeIds")]
+    pub requests: Vec<uuid::Uuid>,
+
+    /// Station order of the trip with all chosen cargo requests inserted.
+    #[serde(rename = "stationIds")]
+    pub stations: Vec<uuid::Uuid>,
+
+    #[serde(rename = "addedDistance")]
+    pub added_distance: u64,
+
+    #[serde(rename = "addedTime")]
+    pub added_time: u64,
+
+    pub metric: Metric,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCorridorPath {
+    pub id: uuid::Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCorridorRequest {
+    /// Largest distance from the trip's road, in meters.
+    pub width: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetCorridorResponse {
+    /// Unassigned cargo requests with both stations within the corridor.
     #[serde(rename = "routeIds")]
     pub requests: Vec<uuid::Uuid>,
 }
@@ -88,6 +372,10 @@ pub struct MergeRoutesRequest {
 
     #[serde(rename = "cargoRequestRouteId")]
     pub requests: Vec<uuid::Uuid>,
+
+    /// Move cargo requests already assigned to another trip instead of failing.
+    #[serde(default)]
+    pub reassign: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -96,6 +384,79 @@ pub struct MergeRoutesResponse {
     pub route: uuid::Uuid,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct GetTripVersionRequest {
+    pub id: uuid::Uuid,
+}
+
+/// One version of a trip. Every merge, unmerge and station removal creates a
+/// new version from its parent.
+#[derive(Serialize, Deserialize)]
+pub struct TripVersion {
+    #[serde(rename = "routeId")]
+    pub route: uuid::Uuid,
+
+    #[serde(rename = "parentRouteId")]
+    pub parent: Option<uuid::Uuid>,
+
+    pub version: i32,
+
+    pub status: Status,
+
+    /// Cargo requests merged into the parent to create this version, empty if
+    /// it was created by detaching cargo requests or removing stations.
+    #[serde(rename = "mergedCargoRequestRouteIds")]
+    pub merged_requests: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GetTripHistoryResponse {
+    /// All versions of the trip, oldest first.
+    pub versions: Vec<TripVersion>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MergePreviewLeg {
+    #[serde(rename = "fromStationId")]
+    pub from_station: uuid::Uuid,
+
+    #[serde(rename = "toStationId")]
+    pub to_station: uuid::Uuid,
+
+    pub distance: u64,
+
+    #[serde(rename = "tripTime")]
+    pub trip_time: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MergePreviewResponse {
+    /// Station order the merged trip would have.
+    #[serde(rename = "stationIds")]
+    pub stations: Vec<uuid::Uuid>,
+
+    pub legs: Vec<MergePreviewLeg>,
+
+    pub distance: u64,
+
+    #[serde(rename = "tripTime")]
+    pub trip_time: u64,
+
+    /// Compared to the trip before merging. Can be negative when the merged
+    /// requests' stations happen to give a shorter route.
+    #[serde(rename = "addedDistance")]
+    pub added_distance: i64,
+
+    #[serde(rename = "addedTime")]
+    pub added_time: i64,
+
+    /// When every station in `stationIds` is served, if the trip has a departure time.
+    #[serde(rename = "arrivalTimes", skip_serializing_if = "Vec::is_empty")]
+    pub arrivals: Vec<i64>,
+
+    pub points: Vec<[f64; 2]>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RemoveStationsRequest {
     #[serde(rename = "deleteStationIds")]
@@ -105,7 +466,65 @@ pub struct RemoveStationsRequest {
     pub trip: uuid::Uuid,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct RemoveStationsResponse {
+    /// New version of the trip without the stations.
+    #[serde(rename = "routeId")]
+    pub route: uuid::Uuid,
+
+    #[serde(rename = "detachedCargoRequestRouteIds")]
+    pub detached_requests: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeStatusPath {
+    pub id: uuid::Uuid,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeStatusRequest {
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeStatusResponse {
+    #[serde(rename = "routeId")]
+    pub route: uuid::Uuid,
+
+    pub status: Status,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnmergeRoutesRequest {
+    #[serde(rename = "tripRouteId")]
+    pub trip: uuid::Uuid,
+
+    #[serde(rename = "cargoRequestRouteIds")]
+    pub requests: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnmergeRoutesResponse {
+    /// New version of the trip without the cargo requests.
+    #[serde(rename = "routeId")]
+    pub route: uuid::Uuid,
+
+    /// Stations of the trip once the cargo requests are detached.
+    #[serde(rename = "stationIds")]
+    pub stations: Vec<uuid::Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorDetail {
+    pub target: String,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ErrorResponse {
+    pub code: String,
     pub message: String,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
 }
@@ -0,0 +1,311 @@
+//! Field-level checks of request bodies, run before any map service call or
+//! transaction.
+//!
+//! Every problem found is reported as an [`ErrorDetail`] whose `target` is the
+//! JSON path of the offending field, e.g. `fromStation.coords.lat`.
+
+use crate::types::Coord;
+
+use super::Error;
+use super::endpoints::Result;
+use super::types::{
+    Capacity, Cargo, CreateRouteRequest, ErrorDetail, ListStationsRequest, ScoringOptions, Station,
+    TimeWindow, UnmergeRoutesRequest, UpsertStationsRequest,
+};
+
+/// Most stations a single bulk upsert may carry.
+const MAX_UPSERT: usize = 1000;
+
+/// Most stations a single page of the station list may hold.
+const MAX_PAGE: i64 = 1000;
+
+/// Collects the problems found in a request.
+#[derive(Default)]
+pub struct Problems(Vec<ErrorDetail>);
+
+impl Problems {
+    pub fn add(&mut self, target: &str, message: impl Into<String>) {
+        self.0.push(ErrorDetail {
+            target: target.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// Fails with every problem found, if any.
+    pub fn into_result(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidInput(self.0))
+        }
+    }
+}
+
+/// A value that can check its own fields.
+pub trait Validate {
+    /// Adds the problems of `self`, found at `target`, to `problems`.
+    fn validate(&self, target: &str, problems: &mut Problems);
+
+    /// Checks `self` as the whole request body.
+    fn check(&self) -> Result<()> {
+        let mut problems = Problems::default();
+        self.validate("", &mut problems);
+        problems.into_result()
+    }
+}
+
+/// Joins a field name onto the path of its parent.
+fn field(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}.{name}")
+    }
+}
+
+/// Checks an amount of cargo or capacity.
+fn amount(problems: &mut Problems, target: &str, value: f64) {
+    if !(value >= 0.0 && value.is_finite()) {
+        problems.add(
+            target,
+            format!("must be a non-negative number, got {value}"),
+        );
+    }
+}
+
+/// Checks a latitude (`limit` 90) or longitude (`limit` 180).
+fn axis(problems: &mut Problems, target: &str, value: f64, limit: f64) {
+    if !value.is_finite() {
+        problems.add(target, format!("must be a number, got {value}"));
+    } else if value.abs() > limit {
+        problems.add(
+            target,
+            format!("must be between -{limit} and {limit}, got {value}"),
+        );
+    }
+}
+
+impl Validate for Coord {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        axis(problems, &field(target, "lat"), self.lat, 90.0);
+        axis(problems, &field(target, "lon"), self.lon, 180.0);
+    }
+}
+
+impl Validate for Station {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        if self.address.trim().is_empty() {
+            problems.add(&field(target, "address"), "must not be empty");
+        }
+
+        self.coords.validate(&field(target, "coords"), problems);
+    }
+}
+
+impl Validate for TimeWindow {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        if let (Some(earliest), Some(latest)) = (self.earliest, self.latest)
+            && earliest > latest
+        {
+            problems.add(
+                &field(target, "latest"),
+                format!("must not be before earliest {earliest}, got {latest}"),
+            );
+        }
+    }
+}
+
+impl Validate for Cargo {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        amount(problems, &field(target, "weight"), self.weight);
+        amount(problems, &field(target, "volume"), self.volume);
+        amount(problems, &field(target, "pallets"), self.pallets as f64);
+    }
+}
+
+impl Validate for Capacity {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        let amounts = [
+            ("weight", self.weight),
+            ("volume", self.volume),
+            ("pallets", self.pallets.map(|p| p as f64)),
+        ];
+
+        for (name, value) in amounts {
+            if let Some(value) = value {
+                amount(problems, &field(target, name), value);
+            }
+        }
+    }
+}
+
+impl Validate for CreateRouteRequest {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        self.from_station
+            .validate(&field(target, "fromStation"), problems);
+        self.to_station
+            .validate(&field(target, "toStation"), problems);
+
+        if self.from_station.id == self.to_station.id {
+            problems.add(
+                &field(target, "toStation.id"),
+                "must differ from fromStation.id",
+            );
+        }
+
+        if let Some(window) = &self.from_window {
+            window.validate(&field(target, "fromWindow"), problems);
+        }
+
+        if let Some(window) = &self.to_window {
+            window.validate(&field(target, "toWindow"), problems);
+        }
+
+        if let Some(cargo) = &self.cargo {
+            cargo.validate(&field(target, "cargo"), problems);
+        }
+
+        if let Some(capacity) = &self.capacity {
+            capacity.validate(&field(target, "capacity"), problems);
+        }
+    }
+}
+
+/// Checks a route as a cargo request, which has time windows and cargo, or
+/// as a trip, which has a departure time and a capacity.
+pub fn check_route(r: &CreateRouteRequest, is_request: bool) -> Result<()> {
+    let mut problems = Problems::default();
+    r.validate("", &mut problems);
+
+    let fields = [
+        ("fromWindow", r.from_window.is_some(), true),
+        ("toWindow", r.to_window.is_some(), true),
+        ("cargo", r.cargo.is_some(), true),
+        ("departureTime", r.departure.is_some(), false),
+        ("capacity", r.capacity.is_some(), false),
+    ];
+
+    for (name, given, of_request) in fields {
+        if given && of_request != is_request {
+            let kind = if is_request {
+                "a cargo request"
+            } else {
+                "a trip"
+            };
+            problems.add(name, format!("must not be given for {kind}"));
+        }
+    }
+
+    problems.into_result()
+}
+
+impl Validate for ScoringOptions {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        let limits = [
+            ("maxAddedDistance", self.max_added_distance),
+            ("maxAddedTime", self.max_added_time),
+            ("maxDetourPercent", self.max_detour_percent),
+        ];
+
+        for (name, value) in limits {
+            if let Some(value) = value {
+                amount(problems, &field(target, name), value);
+            }
+        }
+    }
+}
+
+impl Validate for UnmergeRoutesRequest {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        if self.requests.is_empty() {
+            problems.add(
+                &field(target, "cargoRequestRouteIds"),
+                "must hold at least one cargo request",
+            );
+        }
+    }
+}
+
+impl Validate for UpsertStationsRequest {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        let stations = field(target, "stations");
+
+        if self.stations.len() > MAX_UPSERT {
+            problems.add(
+                &stations,
+                format!(
+                    "must hold at most {MAX_UPSERT} stations, got {}",
+                    self.stations.len()
+                ),
+            );
+        }
+
+        for (index, station) in self.stations.iter().enumerate() {
+            let target = format!("{stations}[{index}]");
+            station.validate(&target, problems);
+
+            if let Some(first) = self.stations[..index]
+                .iter()
+                .position(|s| s.id == station.id)
+            {
+                problems.add(
+                    &field(&target, "id"),
+                    format!("repeats {stations}[{first}].id"),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for ListStationsRequest {
+    fn validate(&self, target: &str, problems: &mut Problems) {
+        let corners = [
+            ("minLat", self.min_lat, 90.0),
+            ("minLon", self.min_lon, 180.0),
+            ("maxLat", self.max_lat, 90.0),
+            ("maxLon", self.max_lon, 180.0),
+        ];
+
+        if corners.iter().any(|c| c.1.is_some()) {
+            for (name, value, limit) in corners {
+                match value {
+                    Some(value) => axis(problems, &field(target, name), value, limit),
+                    None => problems.add(
+                        &field(target, name),
+                        "must be given with the rest of the bounding box",
+                    ),
+                }
+            }
+        }
+
+        let ranges = [
+            ("Lat", self.min_lat, self.max_lat),
+            ("Lon", self.min_lon, self.max_lon),
+        ];
+
+        for (name, min, max) in ranges {
+            if let (Some(min), Some(max)) = (min, max)
+                && min > max
+            {
+                problems.add(
+                    &field(target, &format!("max{name}")),
+                    format!("must not be less than min{name} {min}, got {max}"),
+                );
+            }
+        }
+
+        if !(1..=MAX_PAGE).contains(&self.limit) {
+            problems.add(
+                &field(target, "limit"),
+                format!("must be between 1 and {MAX_PAGE}, got {}", self.limit),
+            );
+        }
+
+        if self.offset < 0 {
+            problems.add(
+                &field(target, "offset"),
+                format!("must not be negative, got {}", self.offset),
+            );
+        }
+    }
+}
@@ -1,41 +1,128 @@
 use std::env::VarError;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::anyhow;
 
+use crate::api::map_service::AxisOrder;
+use crate::api::service::planner::{Limits, Objective};
+use crate::api::service::spatial::Storage;
+
 const ENV_POSTGRES_URL: &str = "PG_URL";
 const ENV_LISTEN_PORT: &str = "LISTEN_PORT";
 const ENV_MAP_SERVICE_ADDR: &str = "MAP_SERVICE_ADDR";
+const ENV_MAP_SERVICE_TIMEOUT_MS: &str = "MAP_SERVICE_TIMEOUT_MS";
+const ENV_MAP_SERVICE_RETRIES: &str = "MAP_SERVICE_RETRIES";
+const ENV_MAP_SERVICE_BACKOFF_MS: &str = "MAP_SERVICE_BACKOFF_MS";
+const ENV_MAP_SERVICE_BREAKER_THRESHOLD: &str = "MAP_SERVICE_BREAKER_THRESHOLD";
+const ENV_MAP_SERVICE_BREAKER_COOLDOWN_MS: &str = "MAP_SERVICE_BREAKER_COOLDOWN_MS";
+const ENV_MAP_SERVICE_CONCURRENCY: &str = "MAP_SERVICE_CONCURRENCY";
+const ENV_MAP_SERVICE_MULTI_STOP: &str = "MAP_SERVICE_MULTI_STOP";
+const ENV_MAP_SERVICE_AXIS_ORDER: &str = "MAP_SERVICE_AXIS_ORDER";
+const ENV_MAX_ADDED_DISTANCE: &str = "MAX_ADDED_DISTANCE";
+const ENV_MAX_ADDED_TIME: &str = "MAX_ADDED_TIME";
+const ENV_MAX_DETOUR_PERCENT: &str = "MAX_DETOUR_PERCENT";
+const ENV_OBJECTIVE: &str = "OBJECTIVE";
+const ENV_STORAGE: &str = "STORAGE";
 
 pub const REQUIRED_VARIABLES: [&str; 2] = [ENV_POSTGRES_URL, ENV_MAP_SERVICE_ADDR];
 
 const DEFAULT_LISTEN_PORT: u16 = 9616;
+const DEFAULT_MAP_SERVICE_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_MAP_SERVICE_RETRIES: u32 = 2;
+const DEFAULT_MAP_SERVICE_BACKOFF_MS: u64 = 200;
+const DEFAULT_MAP_SERVICE_BREAKER_THRESHOLD: u32 = 5;
+const DEFAULT_MAP_SERVICE_BREAKER_COOLDOWN_MS: u64 = 30000;
+const DEFAULT_MAP_SERVICE_CONCURRENCY: usize = 8;
+const DEFAULT_MAX_ADDED_DISTANCE: f64 = 10000.0;
 
 pub struct Config {
     pub pg_url: String,
     pub listen_port: u16,
     pub map_service_addr: String,
+    pub map_service_timeout: Duration,
+    pub map_service_retries: u32,
+    pub map_service_backoff: Duration,
+    pub map_service_breaker_threshold: u32,
+    pub map_service_breaker_cooldown: Duration,
+    pub map_service_concurrency: usize,
+    pub map_service_multi_stop: bool,
+    pub map_service_axis_order: AxisOrder,
+    pub limits: Limits,
+    pub storage: Storage,
 }
 
 impl Config {
     pub fn env() -> anyhow::Result<Self> {
         let postgres_url = env(ENV_POSTGRES_URL)?;
         let map_service_addr = env(ENV_MAP_SERVICE_ADDR)?;
-        let listen_port = env(ENV_LISTEN_PORT)
-            .and_then(|v| v.parse().map_err(Into::into))
-            .unwrap_or(DEFAULT_LISTEN_PORT);
+        let listen_port = env_or(ENV_LISTEN_PORT, DEFAULT_LISTEN_PORT);
 
         Ok(Self {
             pg_url: postgres_url,
             listen_port,
             map_service_addr,
+            map_service_timeout: Duration::from_millis(env_or(
+                ENV_MAP_SERVICE_TIMEOUT_MS,
+                DEFAULT_MAP_SERVICE_TIMEOUT_MS,
+            )),
+            map_service_retries: env_or(ENV_MAP_SERVICE_RETRIES, DEFAULT_MAP_SERVICE_RETRIES),
+            map_service_backoff: Duration::from_millis(env_or(
+                ENV_MAP_SERVICE_BACKOFF_MS,
+                DEFAULT_MAP_SERVICE_BACKOFF_MS,
+            )),
+            map_service_breaker_threshold: env_or(
+                ENV_MAP_SERVICE_BREAKER_THRESHOLD,
+                DEFAULT_MAP_SERVICE_BREAKER_THRESHOLD,
+            ),
+            map_service_breaker_cooldown: Duration::from_millis(env_or(
+                ENV_MAP_SERVICE_BREAKER_COOLDOWN_MS,
+                DEFAULT_MAP_SERVICE_BREAKER_COOLDOWN_MS,
+            )),
+            map_service_concurrency: env_or(
+                ENV_MAP_SERVICE_CONCURRENCY,
+                DEFAULT_MAP_SERVICE_CONCURRENCY,
+            ),
+            map_service_multi_stop: env_or(ENV_MAP_SERVICE_MULTI_STOP, false),
+            map_service_axis_order: env_parse(ENV_MAP_SERVICE_AXIS_ORDER, AxisOrder::default())?,
+            limits: Limits {
+                max_added_distance: env_parse(
+                    ENV_MAX_ADDED_DISTANCE,
+                    Limit(Some(DEFAULT_MAX_ADDED_DISTANCE)),
+                )?
+                .0,
+                max_added_time: env_parse(ENV_MAX_ADDED_TIME, Limit(None))?.0,
+                max_detour_percent: env_parse(ENV_MAX_DETOUR_PERCENT, Limit(None))?.0,
+                objective: env_parse(ENV_OBJECTIVE, Objective::default())?,
+            },
+            storage: env_parse(ENV_STORAGE, Storage::default())?,
         })
     }
 
+    /// Reads only the database settings, for commands that do not serve requests.
+    pub fn pg_url_env() -> anyhow::Result<String> {
+        env(ENV_POSTGRES_URL)
+    }
+
     pub fn log(&self) {
         log::info!("CONFIG:");
         log::info!("POSTGRES URL:        {}", self.pg_url);
         log::info!("LISTEN PORT:         {}", self.listen_port);
         log::info!("MAP SERVICE ADDRESS: {}", self.map_service_addr);
+        log::info!("MAP SERVICE TIMEOUT: {:?}", self.map_service_timeout);
+        log::info!("MAP SERVICE RETRIES: {}", self.map_service_retries);
+        log::info!("MAP SERVICE BACKOFF: {:?}", self.map_service_backoff);
+        log::info!(
+            "MAP SERVICE BREAKER: {} failures, {:?} cooldown",
+            self.map_service_breaker_threshold,
+            self.map_service_breaker_cooldown
+        );
+        log::info!("MAP SERVICE CONCURRENCY: {}", self.map_service_concurrency);
+        log::info!("MAP SERVICE MULTI STOP: {}", self.map_service_multi_stop);
+        log::info!("MAP SERVICE AXIS ORDER: {:?}", self.map_service_axis_order);
+        log::info!("DETOUR LIMITS:       {:?}", self.limits);
+        log::info!("STORAGE:             {:?}", self.storage);
     }
 }
 
@@ -45,3 +132,45 @@ fn env(name: &str) -> anyhow::Result<String> {
         VarError::NotUnicode(_) => anyhow!("{name} value is not valid unicode"),
     })
 }
+
+/// Reads an optional variable, falling back to `default` when it is unset or invalid.
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads an optional variable, falling back to `default` only when it is unset.
+/// A value that does not parse is an error, so that typos do not go unnoticed.
+fn env_parse<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if std::env::var_os(name).is_none() {
+        return Ok(default);
+    }
+
+    env(name)?.parse().map_err(|e| anyhow!("{name}: {e}"))
+}
+
+/// A detour limit, a non-negative number or `unlimited`.
+struct Limit(Option<f64>);
+
+impl FromStr for Limit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "unlimited" {
+            return Ok(Limit(None));
+        }
+
+        match s.parse::<f64>() {
+            Ok(value) if value >= 0.0 && value.is_finite() => Ok(Limit(Some(value))),
+            _ => Err(format!(
+                "expected a non-negative number or unlimited, got {s:?}"
+            )),
+        }
+    }
+}
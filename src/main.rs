@@ -1,13 +1,27 @@
+use anyhow::anyhow;
+use gw_routes::api::map_service;
+use gw_routes::api::service::spatial;
 use gw_routes::config::{Config, REQUIRED_VARIABLES};
 use gw_routes::db::Database;
-use gw_routes::schema::SCHEMA;
+use gw_routes::schema;
+
+const USAGE: &str = "usage: gw-routes [migrate [status | up [VERSION] | down VERSION]]";
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    if let Err(e) = run().await {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        None => run().await,
+        Some("migrate") => migrate(&args[1..]).await,
+        Some(command) => Err(anyhow!("unknown command {command}. {USAGE}")),
+    };
+
+    if let Err(e) = result {
         log::error!("{e}");
+        std::process::exit(1);
     }
 }
 
@@ -24,13 +38,32 @@ async fn run() -> anyhow::Result<()> {
     let database = Database::connect(&config.pg_url).await?;
     log::info!("Connected to database ({})", config.pg_url);
 
-    sqlx::raw_sql(SCHEMA).execute(&database.pool).await?;
-    log::info!("Successfully ran init query");
+    let version = schema::migrate_up(&database.pool, None)
+        .await
+        .map_err(|e| anyhow!("{e}. Refusing to start"))?;
+    log::info!("Database schema is at version {version}");
 
-    let client = gw_routes::api::map_service::client::Client::new(&config.map_service_addr)?;
+    spatial::prepare(&database.pool, config.storage)
+        .await
+        .map_err(|e| anyhow!("{e}. Refusing to start"))?;
+
+    let client = map_service::Client::with_config(
+        &config.map_service_addr,
+        map_service::ClientConfig {
+            timeout: config.map_service_timeout,
+            retries: config.map_service_retries,
+            backoff: config.map_service_backoff,
+            breaker_threshold: config.map_service_breaker_threshold,
+            breaker_cooldown: config.map_service_breaker_cooldown,
+            concurrency: config.map_service_concurrency,
+            multi_stop: config.map_service_multi_stop,
+            axis_order: config.map_service_axis_order,
+        },
+    )?;
     log::info!("Connected to map service ({})", config.map_service_addr);
 
-    let state = gw_routes::api::service::State::new(database, client);
+    let state =
+        gw_routes::api::service::State::new(database, client, config.limits, config.storage);
 
     let listen_addr = format!("0.0.0.0:{}", config.listen_port);
     let listener = tokio::net::TcpListener::bind(&listen_addr).await?;
@@ -42,3 +75,36 @@ async fn run() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+async fn migrate(args: &[String]) -> anyhow::Result<()> {
+    let parse_version = |arg: Option<&String>| -> anyhow::Result<Option<i32>> {
+        arg.map(|v| v.parse().map_err(|e| anyhow!("invalid version {v}: {e}")))
+            .transpose()
+    };
+
+    let pg_url = Config::pg_url_env()?;
+    let database = Database::connect(&pg_url).await?;
+
+    let version = match args.first().map(String::as_str) {
+        None | Some("up") => {
+            schema::migrate_up(&database.pool, parse_version(args.get(1))?).await?
+        }
+        Some("down") => {
+            let target = parse_version(args.get(1))?
+                .ok_or_else(|| anyhow!("migrate down needs a target version. {USAGE}"))?;
+            schema::migrate_down(&database.pool, target).await?
+        }
+        Some("status") => {
+            let mut conn = database.pool.acquire().await?;
+            schema::current_version(&mut conn).await?
+        }
+        Some(command) => return Err(anyhow!("unknown migrate command {command}. {USAGE}")),
+    };
+
+    println!(
+        "schema version: {version} (latest known: {})",
+        schema::latest_version()
+    );
+
+    Ok(())
+}
@@ -1,36 +1,214 @@
-pub const SCHEMA: &'static str = r#"
+use anyhow::anyhow;
+use sqlx::Connection;
 
-CREATE TABLE IF NOT EXISTS station (
-    id UUID PRIMARY KEY,
-    address TEXT NOT NULL,
-    coords POINT NOT NULL
-);
+/// Key for the advisory lock held while migrations run, so that several
+/// instances starting at once do not apply the same migration twice.
+const MIGRATION_LOCK: i64 = 0x6777_726f_7574_6573;
 
-CREATE TABLE IF NOT EXISTS trip (
-    id UUID PRIMARY KEY,
-    source UUID REFERENCES station (id) NOT NULL,
-    destination UUID REFERENCES station (id) NOT NULL
+const VERSION_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_version (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
 );
+"#;
 
-CREATE TABLE IF NOT EXISTS request (
-    id UUID PRIMARY KEY,
-    trip_id UUID REFERENCES trip (id),
-    source UUID REFERENCES station (id) NOT NULL,
-    destination UUID REFERENCES station (id) NOT NULL
-);
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
 
-CREATE TABLE IF NOT EXISTS segment (
-    s1 UUID REFERENCES station (id) NOT NULL,
-    s2 UUID REFERENCES station (id) NOT NULL,
-    points POINT[] NOT NULL,
-    distance INTEGER NOT NULL,
-    time INTEGER NOT NULL
-);
+macro_rules! migration {
+    ($version:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+        }
+    };
+}
 
-CREATE TABLE IF NOT EXISTS path (
-    trip_id UUID REFERENCES trip (id) NOT NULL,
-    station_id UUID REFERENCES station (id) NOT NULL,
-    index INTEGER NOT NULL
-);
+/// All known migrations, ordered by version.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_init"),
+    migration!(2, "0002_segment_path_keys"),
+    migration!(3, "0003_trip_versions"),
+    migration!(4, "0004_statuses"),
+    migration!(5, "0005_time_windows"),
+    migration!(6, "0006_capacity"),
+    migration!(7, "0007_coords_lon_lat"),
+];
 
-"#;
+/// Geography columns and indexes of PostGIS storage. Safe to apply again.
+const POSTGIS: &str = include_str!("../migrations/postgis.sql");
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Returns the version the database is currently at, 0 for an empty database.
+pub async fn current_version(conn: &mut sqlx::PgConnection) -> anyhow::Result<i32> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('schema_version') IS NOT NULL;")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    if !tracked {
+        return Ok(0);
+    }
+
+    let version: Option<i32> = sqlx::query_scalar("SELECT max(version) FROM schema_version;")
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(version.unwrap_or(0))
+}
+
+/// Fails if the database was migrated by a newer build of the service.
+pub fn check_version(current: i32) -> anyhow::Result<()> {
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(anyhow!(
+            "database schema version {current} is newer than the latest known migration {latest}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Applies pending migrations up to `target` (or the latest one).
+/// Returns the resulting schema version.
+pub async fn migrate_up(pool: &sqlx::PgPool, target: Option<i32>) -> anyhow::Result<i32> {
+    let target = target.unwrap_or_else(latest_version);
+
+    with_lock(pool, async |conn| {
+        let current = current_version(conn).await?;
+        check_version(current)?;
+
+        if current == 0 {
+            sqlx::raw_sql(VERSION_TABLE).execute(&mut *conn).await?;
+        }
+
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|m| m.version > current && m.version <= target)
+        {
+            let mut tx = conn.begin().await?;
+
+            sqlx::raw_sql(migration.up)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "migration {} ({}) failed: {e}",
+                        migration.version,
+                        migration.name
+                    )
+                })?;
+
+            sqlx::query("INSERT INTO schema_version (version, name) VALUES ($1, $2);")
+                .bind(migration.version)
+                .bind(migration.name)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+            log::info!(
+                "Applied migration {} ({})",
+                migration.version,
+                migration.name
+            );
+        }
+
+        current_version(conn).await
+    })
+    .await
+}
+
+/// Reverts applied migrations until the schema is at `target`.
+/// Returns the resulting schema version.
+pub async fn migrate_down(pool: &sqlx::PgPool, target: i32) -> anyhow::Result<i32> {
+    with_lock(pool, async |conn| {
+        let current = current_version(conn).await?;
+        check_version(current)?;
+
+        for migration in MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|m| m.version <= current && m.version > target)
+        {
+            let mut tx = conn.begin().await?;
+
+            sqlx::raw_sql(migration.down)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "reverting migration {} ({}) failed: {e}",
+                        migration.version,
+                        migration.name
+                    )
+                })?;
+
+            sqlx::query("DELETE FROM schema_version WHERE version = $1;")
+                .bind(migration.version)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+            log::info!(
+                "Reverted migration {} ({})",
+                migration.version,
+                migration.name
+            );
+        }
+
+        current_version(conn).await
+    })
+    .await
+}
+
+/// Adds the geography columns and indexes PostGIS storage queries. Fails if
+/// the postgis extension is not installed.
+pub async fn enable_postgis(pool: &sqlx::PgPool) -> anyhow::Result<()> {
+    with_lock(pool, async |conn| {
+        let mut tx = conn.begin().await?;
+
+        sqlx::raw_sql(POSTGIS)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "enabling PostGIS storage failed: {e}. Install postgis or use STORAGE=plain"
+                )
+            })?;
+
+        tx.commit().await?;
+        Ok(())
+    })
+    .await
+}
+
+async fn with_lock<T>(
+    pool: &sqlx::PgPool,
+    f: impl AsyncFnOnce(&mut sqlx::PgConnection) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut conn = pool.acquire().await?;
+
+    sqlx::query("SELECT pg_advisory_lock($1);")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *conn)
+        .await?;
+
+    let result = f(&mut conn).await;
+
+    sqlx::query("SELECT pg_advisory_unlock($1);")
+        .bind(MIGRATION_LOCK)
+        .execute(&mut *conn)
+        .await?;
+
+    result
+}
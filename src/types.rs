@@ -1,4 +1,66 @@
+//! Geographic point shared by the database, the map service and the API.
+//!
+//! Inside the service a point is always a [`Coord`]. The axis order only
+//! matters at the boundaries, where it is converted explicitly:
+//! - database: `POINT(x, y)` is `(lon, lat)`, as in PostGIS;
+//! - map service: see [`crate::api::map_service::AxisOrder`];
+//! - API: `{"lat": .., "lon": ..}` objects, and `[lat, lon]` arrays via [`Coord::to_lat_lon`].
+
+use serde::{Deserialize, Serialize};
+use sqlx::Postgres;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::types::PgPoint;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo, PgValueRef};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 pub struct Coord {
     pub lat: f64,
     pub lon: f64,
 }
+
+impl Coord {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    pub fn to_lat_lon(self) -> [f64; 2] {
+        [self.lat, self.lon]
+    }
+}
+
+impl From<Coord> for PgPoint {
+    fn from(c: Coord) -> Self {
+        PgPoint { x: c.lon, y: c.lat }
+    }
+}
+
+impl From<PgPoint> for Coord {
+    fn from(p: PgPoint) -> Self {
+        Coord { lat: p.y, lon: p.x }
+    }
+}
+
+impl sqlx::Type<Postgres> for Coord {
+    fn type_info() -> PgTypeInfo {
+        <PgPoint as sqlx::Type<Postgres>>::type_info()
+    }
+}
+
+impl PgHasArrayType for Coord {
+    fn array_type_info() -> PgTypeInfo {
+        <PgPoint as PgHasArrayType>::array_type_info()
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Postgres> for Coord {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(<PgPoint as sqlx::Decode<Postgres>>::decode(value)?.into())
+    }
+}
+
+impl sqlx::Encode<'_, Postgres> for Coord {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        <PgPoint as sqlx::Encode<Postgres>>::encode(PgPoint::from(*self), buf)
+    }
+}
@@ -0,0 +1,187 @@
+//! Runs the service against a real database and a fake map service.
+//!
+//! The database named by `TEST_PG_URL` is wiped by every test. The tests
+//! using [`Harness`] are `#[ignore]`d, run them with `cargo test -- --ignored`.
+
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use axum::Json;
+use axum::routing::post;
+use serde_json::{Value, json};
+
+use gw_routes::api::map_service;
+use gw_routes::api::service::{self, planner, spatial};
+use gw_routes::db::Database;
+use gw_routes::schema;
+use gw_routes::types::Coord;
+
+/// Meters per second of every leg the fake map service returns.
+pub const SPEED: f64 = 10.0;
+
+/// Tests share one database, so they run one at a time.
+static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub struct Harness {
+    pub pool: sqlx::PgPool,
+    /// Stops of every call made to the fake map service.
+    pub calls: Arc<Mutex<Vec<Vec<[f64; 2]>>>>,
+    base: String,
+    http: reqwest::Client,
+    _lock: tokio::sync::MutexGuard<'static, ()>,
+}
+
+/// Straight legs between the stops, one point in the middle of each.
+async fn fake_route(
+    calls: Arc<Mutex<Vec<Vec<[f64; 2]>>>>,
+    Json(r): Json<map_service::CreateRouteRequest>,
+) -> Json<Value> {
+    calls.lock().unwrap().push(r.stops.clone());
+
+    let mut way = vec![r.stops[0]];
+    let mut legs = Vec::new();
+
+    for pair in r.stops.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let distance = planner::distance(&Coord::new(a[0], a[1]), &Coord::new(b[0], b[1]));
+
+        way.extend([[(a[0] + b[0]) / 2.0, (a[1] + b[1]) / 2.0], b]);
+        legs.push(json!({"distance": distance, "duration": distance / SPEED}));
+    }
+
+    let distance: f64 = legs
+        .iter()
+        .map(|leg| leg["distance"].as_f64().unwrap())
+        .sum();
+
+    Json(json!({
+        "way": way,
+        "distance": distance,
+        "duration": distance / SPEED,
+        "legs": legs,
+    }))
+}
+
+async fn serve(router: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    format!("http://{addr}")
+}
+
+impl Harness {
+    pub async fn start() -> Self {
+        let pg_url =
+            std::env::var("TEST_PG_URL").expect("TEST_PG_URL must name a database to wipe");
+
+        let lock = LOCK.lock().await;
+
+        let database = Database::connect(&pg_url).await.unwrap();
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public;")
+            .execute(&database.pool)
+            .await
+            .unwrap();
+        schema::migrate_up(&database.pool, None).await.unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let map = axum::Router::new().route(
+            "/api/create_route",
+            post({
+                let calls = calls.clone();
+                move |body| fake_route(calls, body)
+            }),
+        );
+        let client = map_service::Client::new(&serve(map).await).unwrap();
+
+        let pool = database.pool.clone();
+        let state = service::State::new(
+            database,
+            client,
+            planner::Limits {
+                max_added_distance: None,
+                max_added_time: None,
+                max_detour_percent: None,
+                objective: planner::Objective::Distance,
+            },
+            spatial::Storage::Plain,
+        );
+        let base = serve(service::router::router(state)).await;
+
+        Self {
+            pool,
+            calls,
+            base,
+            http: reqwest::Client::new(),
+            _lock: lock,
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> (u16, Value) {
+        let response = request.send().await.unwrap();
+        let status = response.status().as_u16();
+        let body = response.json().await.unwrap_or(Value::Null);
+
+        (status, body)
+    }
+
+    pub async fn get(&self, path: &str) -> (u16, Value) {
+        self.send(self.http.get(format!("{}{path}", self.base)))
+            .await
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> (u16, Value) {
+        self.send(self.http.post(format!("{}{path}", self.base)).json(&body))
+            .await
+    }
+
+    pub async fn put(&self, path: &str, body: Value) -> (u16, Value) {
+        self.send(self.http.put(format!("{}{path}", self.base)).json(&body))
+            .await
+    }
+
+    pub async fn delete(&self, path: &str) -> (u16, Value) {
+        self.send(self.http.delete(format!("{}{path}", self.base)))
+            .await
+    }
+
+    /// Creates a trip or cargo request and returns its ID.
+    pub async fn create(&self, path: &str, body: Value) -> String {
+        let (status, body) = self.post(path, body).await;
+        assert_eq!(status, 200, "{body}");
+
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Trip a cargo request is assigned to.
+    pub async fn trip_of(&self, request: &str) -> Option<String> {
+        let trip: Option<uuid::Uuid> =
+            sqlx::query_scalar("SELECT trip_id FROM request WHERE id = $1::uuid;")
+                .bind(request)
+                .fetch_one(&self.pool)
+                .await
+                .unwrap();
+
+        trip.map(|trip| trip.to_string())
+    }
+}
+
+/// Station `n` at the given point.
+pub fn station(n: u8, lat: f64, lon: f64) -> Value {
+    json!({
+        "id": format!("00000000-0000-0000-0000-{n:012}"),
+        "address": format!("station {n}"),
+        "coords": {"lat": lat, "lon": lon},
+    })
+}
+
+/// IDs of the stations a trip goes through, in order.
+pub fn station_ids(trip: &Value) -> Vec<String> {
+    trip["stations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|waypoint| waypoint["station"]["id"].as_str().unwrap().to_string())
+        .collect()
+}
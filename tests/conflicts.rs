@@ -0,0 +1,171 @@
+//! Station IDs sent again with other data, under each `onStationConflict` policy.
+
+mod common;
+
+use serde_json::{Value, json};
+
+use common::{Harness, station};
+use gw_routes::types::Coord;
+
+/// Stored address and coordinates of station `n`.
+async fn stored(h: &Harness, n: u8) -> (String, Coord) {
+    sqlx::query_as("SELECT address, coords FROM station WHERE id = $1::uuid;")
+        .bind(format!("00000000-0000-0000-0000-{n:012}"))
+        .fetch_one(&h.pool)
+        .await
+        .unwrap()
+}
+
+/// Cargo request from station 1 moved to the given point, to station 3.
+fn moving_request(lat: f64, policy: Option<&str>) -> Value {
+    let mut body = json!({
+        "fromStation": station(1, lat, 37.0),
+        "toStation": station(3, 55.0, 37.5),
+    });
+
+    if let Some(policy) = policy {
+        body["onStationConflict"] = json!(policy);
+    }
+
+    body
+}
+
+/// A trip from station 1 to station 2.
+async fn setup(h: &Harness) -> String {
+    h.create(
+        "/routes/trips",
+        json!({
+            "fromStation": station(1, 55.0, 37.0),
+            "toStation": station(2, 55.0, 38.0),
+        }),
+    )
+    .await
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn reject_fails_and_writes_nothing() {
+    let h = Harness::start().await;
+    setup(&h).await;
+
+    let (status, body) = h
+        .post(
+            "/routes/cargo_requests",
+            moving_request(56.0, Some("reject")),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+    assert_eq!(body["code"], "conflict");
+
+    assert_eq!(
+        stored(&h, 1).await,
+        ("station 1".to_string(), Coord::new(55.0, 37.0))
+    );
+    let requests: i64 = sqlx::query_scalar("SELECT count(*) FROM request;")
+        .fetch_one(&h.pool)
+        .await
+        .unwrap();
+    assert_eq!(requests, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn keep_is_the_default_and_warns() {
+    let h = Harness::start().await;
+    setup(&h).await;
+
+    for policy in [None, Some("keep")] {
+        let (status, body) = h
+            .post("/routes/cargo_requests", moving_request(56.0, policy))
+            .await;
+        assert_eq!(status, 200, "{body}");
+
+        let warnings = body["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 1, "{body}");
+        assert_eq!(warnings[0]["target"], "fromStation");
+    }
+
+    assert_eq!(
+        stored(&h, 1).await,
+        ("station 1".to_string(), Coord::new(55.0, 37.0))
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn update_moves_the_station_and_refetches_its_segments() {
+    let h = Harness::start().await;
+    let trip = setup(&h).await;
+    let first = h
+        .create("/routes/cargo_requests", moving_request(55.0, None))
+        .await;
+
+    let (_, before) = h.get(&format!("/routes/trips/{trip}")).await;
+
+    let (status, body) = h
+        .post(
+            "/routes/cargo_requests",
+            moving_request(56.0, Some("update")),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.get("warnings").is_none(), "{body}");
+    assert_eq!(stored(&h, 1).await.1, Coord::new(56.0, 37.0));
+
+    // The trip and the first cargo request still start at station 1, their
+    // segments were fetched again from where it stands now.
+    let (status, after) = h.get(&format!("/routes/trips/{trip}")).await;
+    assert_eq!(status, 200, "{after}");
+    assert!(
+        after["stations"][1]["distance"].as_u64() > before["stations"][1]["distance"].as_u64(),
+        "{before} {after}"
+    );
+
+    for path in [
+        format!("/routes/trips/{trip}/points"),
+        format!("/routes/cargo_requests/{first}/points"),
+    ] {
+        let (status, body) = h.get(&path).await;
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(body["points"][0], json!([56.0, 37.0]), "{path}");
+    }
+
+    let stale: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM segment WHERE NOT (points[1] ~= (SELECT coords FROM station WHERE id = s1));",
+    )
+    .fetch_one(&h.pool)
+    .await
+    .unwrap();
+    assert_eq!(stale, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn routes_are_read_without_their_segments() {
+    let h = Harness::start().await;
+    let trip = setup(&h).await;
+    let request = h
+        .create("/routes/cargo_requests", moving_request(55.0, None))
+        .await;
+
+    sqlx::query("DELETE FROM segment;")
+        .execute(&h.pool)
+        .await
+        .unwrap();
+
+    for path in [
+        format!("/routes/trips/{trip}"),
+        format!("/routes/trips/{trip}/points"),
+        format!("/routes/cargo_requests/{request}"),
+        format!("/routes/cargo_requests/{request}/points"),
+    ] {
+        let (status, body) = h.get(&path).await;
+        assert_eq!(status, 200, "{path}: {body}");
+    }
+
+    let (_, body) = h.get(&format!("/routes/trips/{trip}")).await;
+    assert!(
+        body["stations"][1]["distance"].as_u64().unwrap() > 0,
+        "{body}"
+    );
+}
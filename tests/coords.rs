@@ -0,0 +1,59 @@
+//! Axis order of geographic points at every boundary. Latitude and longitude
+//! of the test points differ a lot, so a swap anywhere shows up.
+
+use gw_routes::api::map_service::AxisOrder;
+use gw_routes::api::service::planner;
+use gw_routes::types::Coord;
+use sqlx::postgres::types::PgPoint;
+
+const NOVOSIBIRSK: Coord = Coord {
+    lat: 55.03,
+    lon: 82.92,
+};
+
+#[test]
+fn database_point_is_lon_lat() {
+    let point = PgPoint::from(NOVOSIBIRSK);
+
+    assert_eq!(point.x, NOVOSIBIRSK.lon);
+    assert_eq!(point.y, NOVOSIBIRSK.lat);
+    assert_eq!(Coord::from(point), NOVOSIBIRSK);
+}
+
+#[test]
+fn map_service_axis_order() {
+    assert_eq!(AxisOrder::LatLon.encode(NOVOSIBIRSK), [55.03, 82.92]);
+    assert_eq!(AxisOrder::LonLat.encode(NOVOSIBIRSK), [82.92, 55.03]);
+
+    for order in [AxisOrder::LatLon, AxisOrder::LonLat] {
+        assert_eq!(order.decode(order.encode(NOVOSIBIRSK)), NOVOSIBIRSK);
+    }
+
+    assert_eq!("lon_lat".parse(), Ok(AxisOrder::LonLat));
+    assert_eq!(AxisOrder::default(), AxisOrder::LatLon);
+}
+
+#[test]
+fn json_names_axes() {
+    let json = serde_json::to_value(NOVOSIBIRSK).unwrap();
+
+    assert_eq!(json, serde_json::json!({ "lat": 55.03, "lon": 82.92 }));
+    assert_eq!(NOVOSIBIRSK.to_lat_lon(), [55.03, 82.92]);
+}
+
+#[test]
+fn distance_reads_latitude_and_longitude() {
+    // One degree along a meridian is about 111.2 km everywhere, one degree
+    // along the 60th parallel only half of that.
+    let along_meridian = planner::distance(&Coord::new(60.0, 30.0), &Coord::new(61.0, 30.0));
+    let along_parallel = planner::distance(&Coord::new(60.0, 30.0), &Coord::new(60.0, 31.0));
+
+    assert!(
+        (along_meridian - 111_195.0).abs() < 100.0,
+        "{along_meridian}"
+    );
+    assert!(
+        (along_parallel - 55_597.0).abs() < 100.0,
+        "{along_parallel}"
+    );
+}
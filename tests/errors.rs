@@ -0,0 +1,31 @@
+mod common;
+
+use serde_json::json;
+
+use common::{Harness, station};
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn malformed_requests_get_the_error_body() {
+    let h = Harness::start().await;
+
+    let cases = [
+        (
+            h.post(
+                "/routes/trips",
+                json!({"fromStation": station(1, 55.0, 37.0)}),
+            )
+            .await,
+            "body",
+        ),
+        (h.get("/routes/trips/not-a-uuid").await, "path"),
+        (h.get("/routes/stations?limit=many").await, "query"),
+    ];
+
+    for ((status, body), target) in cases {
+        assert_eq!(status, 422, "{body}");
+        assert_eq!(body["code"], "validation_failed");
+        assert!(body["message"].is_string(), "{body}");
+        assert_eq!(body["details"][0]["target"], target, "{body}");
+    }
+}
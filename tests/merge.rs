@@ -0,0 +1,556 @@
+mod common;
+
+use serde_json::json;
+
+use common::{Harness, station, station_ids};
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn later_versions_keep_earlier_requests() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = |from, to| json!({"fromStation": from, "toStation": to});
+    let first = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(1, 55.0, 37.0), station(2, 55.0, 38.0)),
+        )
+        .await;
+    let second = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.0, 37.6), station(6, 55.0, 37.8)),
+        )
+        .await;
+
+    let merge =
+        |trip: &str, request: &str| json!({"tripRouteId": trip, "cargoRequestRouteId": [request]});
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&trip, &first)).await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&v2, &second)).await;
+    assert_eq!(status, 200, "{body}");
+    let v3 = body["routeId"].as_str().unwrap().to_string();
+
+    assert_eq!(h.trip_of(&first).await.as_deref(), Some(v3.as_str()));
+    assert_eq!(h.trip_of(&second).await.as_deref(), Some(v3.as_str()));
+
+    // Superseded versions can no longer be planned against.
+    let (status, body) = h
+        .post(
+            "/routes/trips/potential",
+            json!({"tripRouteId": v2, "cargoRequestRouteIds": [second]}),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn capacity_counts_cargo_on_board() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+                "capacity": {"weight": 1000.0},
+            }),
+        )
+        .await;
+    let request = |from, to, weight| json!({"fromStation": from, "toStation": to, "cargo": {"weight": weight}});
+    let first = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(1, 55.0, 37.0), station(2, 55.0, 38.0), 600.0),
+        )
+        .await;
+    let second = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.0, 37.6), station(6, 55.0, 37.8), 300.0),
+        )
+        .await;
+    let overlapping = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(7, 55.0, 37.3), station(8, 55.0, 37.35), 600.0),
+        )
+        .await;
+
+    let merge =
+        |trip: &str, request: &str| json!({"tripRouteId": trip, "cargoRequestRouteId": [request]});
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&trip, &first)).await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&v2, &second)).await;
+    assert_eq!(status, 200, "{body}");
+    let v3 = body["routeId"].as_str().unwrap().to_string();
+
+    // The first request's cargo, on board the whole way, still counts
+    // against the capacity.
+    let (status, body) = h
+        .post("/routes/trips/merge", merge(&v3, &overlapping))
+        .await;
+    assert_eq!(status, 422, "{body}");
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/unmerge",
+            json!({"tripRouteId": v3, "cargoRequestRouteIds": [first]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v4 = body["routeId"].as_str().unwrap().to_string();
+    assert_eq!(h.trip_of(&first).await, None);
+
+    let (status, body) = h.get(&format!("/routes/trips/{v4}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(
+        station_ids(&body),
+        [1, 5, 6, 2].map(|n| format!("00000000-0000-0000-0000-{n:012}"))
+    );
+
+    // Without the first request's cargo there is room again.
+    let (status, body) = h
+        .post("/routes/trips/merge", merge(&v4, &overlapping))
+        .await;
+    assert_eq!(status, 200, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn duplicate_requests_are_rejected() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    let calls = h.calls.lock().unwrap().len();
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request, request]}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+    assert_eq!(h.calls.lock().unwrap().len(), calls);
+    assert_eq!(h.trip_of(&request).await, None);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn unmerge_creates_a_version() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    // Nothing to detach would only copy the trip.
+    let (status, body) = h
+        .post(
+            "/routes/trips/unmerge",
+            json!({"tripRouteId": v2, "cargoRequestRouteIds": []}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+    assert_eq!(body["details"][0]["target"], "cargoRequestRouteIds");
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/unmerge",
+            json!({"tripRouteId": v2, "cargoRequestRouteIds": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v3 = body["routeId"].as_str().unwrap().to_string();
+    assert_ne!(v3, v2);
+    assert_eq!(h.trip_of(&request).await, None);
+
+    let ids = |ns: &[u8]| -> Vec<String> {
+        ns.iter()
+            .map(|n| format!("00000000-0000-0000-0000-{n:012}"))
+            .collect()
+    };
+
+    // Earlier versions keep the stations they had.
+    let (status, body) = h.get(&format!("/routes/trips/{v2}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(station_ids(&body), ids(&[1, 3, 4, 2]));
+
+    let (status, body) = h.get(&format!("/routes/trips/{v3}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(station_ids(&body), ids(&[1, 2]));
+
+    let (status, body) = h.get(&format!("/routes/trips/{v3}/history")).await;
+    assert_eq!(status, 200, "{body}");
+    let versions = body["versions"].as_array().unwrap();
+    assert_eq!(versions.len(), 3);
+    assert_eq!(versions[2]["mergedCargoRequestRouteIds"], json!([]));
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn removing_a_station_detaches_its_requests() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = |from, to| json!({"fromStation": from, "toStation": to});
+    let leaving = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(3, 55.0, 37.2), station(4, 55.0, 37.8)),
+        )
+        .await;
+    let staying = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.0, 37.4), station(6, 55.0, 37.6)),
+        )
+        .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [leaving, staying]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    let ids = |ns: &[u8]| -> Vec<String> {
+        ns.iter()
+            .map(|n| format!("00000000-0000-0000-0000-{n:012}"))
+            .collect()
+    };
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/remove_stations",
+            json!({"tripId": v2, "deleteStationIds": ids(&[3])}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["detachedCargoRequestRouteIds"], json!([leaving]));
+    let v3 = body["routeId"].as_str().unwrap().to_string();
+    assert_ne!(v3, v2);
+
+    assert_eq!(h.trip_of(&leaving).await, None);
+    assert_eq!(h.trip_of(&staying).await.as_deref(), Some(v3.as_str()));
+
+    // The other station of the detached request goes too.
+    let (status, body) = h.get(&format!("/routes/trips/{v3}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(station_ids(&body), ids(&[1, 5, 6, 2]));
+
+    let (status, body) = h.get(&format!("/routes/trips/{v2}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(station_ids(&body), ids(&[1, 3, 5, 6, 4, 2]));
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn combined_routes_stay_within_limits() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = |from, to| json!({"fromStation": from, "toStation": to});
+    let on_the_way = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(3, 55.0, 37.2), station(4, 55.0, 37.4)),
+        )
+        .await;
+    let aside = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.1, 37.5), station(6, 55.1, 37.6)),
+        )
+        .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/potential/combined",
+            json!({
+                "tripRouteId": trip,
+                "cargoRequestRouteIds": [on_the_way, aside],
+                "maxAddedDistance": 1000.0,
+            }),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["routeIds"], json!([on_the_way]));
+    assert!(body["addedDistance"].as_u64().unwrap() <= 1000, "{body}");
+
+    let ids = |ns: &[u8]| -> Vec<String> {
+        ns.iter()
+            .map(|n| format!("00000000-0000-0000-0000-{n:012}"))
+            .collect()
+    };
+    assert_eq!(body["stationIds"], json!(ids(&[1, 3, 4, 2])));
+
+    // Without a limit the request aside is worth taking too.
+    let (status, body) = h
+        .post(
+            "/routes/trips/potential/combined",
+            json!({
+                "tripRouteId": trip,
+                "cargoRequestRouteIds": [on_the_way, aside],
+                "maxAddedDistance": 100000.0,
+            }),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["routeIds"].as_array().unwrap().len(), 2, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn assigned_requests_move_only_with_reassign() {
+    let h = Harness::start().await;
+
+    let trip = |from, to| json!({"fromStation": from, "toStation": to});
+    let first = h
+        .create(
+            "/routes/trips",
+            trip(station(1, 55.0, 37.0), station(2, 55.0, 38.0)),
+        )
+        .await;
+    let second = h
+        .create(
+            "/routes/trips",
+            trip(station(5, 55.0, 37.1), station(6, 55.0, 37.9)),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": first, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let first_v2 = body["routeId"].as_str().unwrap().to_string();
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": second, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+    assert_eq!(body["code"], "conflict");
+    assert_eq!(
+        h.trip_of(&request).await.as_deref(),
+        Some(first_v2.as_str())
+    );
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": second, "cargoRequestRouteId": [request], "reassign": true}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let second_v2 = body["routeId"].as_str().unwrap().to_string();
+    assert_eq!(
+        h.trip_of(&request).await.as_deref(),
+        Some(second_v2.as_str())
+    );
+
+    // The trip it left no longer goes through its stations.
+    let (status, body) = h.get(&format!("/routes/trips/{first_v2}/latest")).await;
+    assert_eq!(status, 200, "{body}");
+    let (status, body) = h
+        .get(&format!(
+            "/routes/trips/{}",
+            body["routeId"].as_str().unwrap()
+        ))
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(
+        station_ids(&body),
+        [1, 2].map(|n| format!("00000000-0000-0000-0000-{n:012}"))
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn preview_writes_nothing_but_segments() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    // The trip's own leg is fetched again when it is not cached.
+    sqlx::query("DELETE FROM segment;")
+        .execute(&h.pool)
+        .await
+        .unwrap();
+
+    let rows = || async {
+        sqlx::query_scalar::<_, String>(
+            "SELECT concat_ws(',',
+                (SELECT count(*) FROM trip),
+                (SELECT count(*) FROM path),
+                (SELECT count(*) FROM request WHERE trip_id IS NOT NULL));",
+        )
+        .fetch_one(&h.pool)
+        .await
+        .unwrap()
+    };
+    let before = rows().await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge/preview",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["legs"].as_array().unwrap().len(), 3, "{body}");
+
+    assert_eq!(rows().await, before);
+    assert_eq!(h.trip_of(&request).await, None);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn trips_without_two_stops_take_no_requests() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    sqlx::query("DELETE FROM path WHERE trip_id = $1::uuid AND index > 0;")
+        .bind(&trip)
+        .execute(&h.pool)
+        .await
+        .unwrap();
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/potential",
+            json!({"tripRouteId": trip, "cargoRequestRouteIds": [request]}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+    assert!(
+        body["message"].as_str().unwrap().contains("two stops"),
+        "{body}"
+    );
+}
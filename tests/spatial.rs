@@ -0,0 +1,111 @@
+//! Spatial lookups with the default plain storage.
+
+mod common;
+
+use serde_json::json;
+
+use common::{Harness, station};
+
+fn id(n: u8) -> String {
+    format!("00000000-0000-0000-0000-{n:012}")
+}
+
+/// A trip along latitude 55 and two cargo requests, one close beside its road
+/// and one a kilometer away from it at the pickup.
+async fn setup(h: &Harness) -> (String, String, String) {
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = |from, to| json!({"fromStation": from, "toStation": to});
+    let beside = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(3, 55.001, 37.2), station(4, 55.001, 37.8)),
+        )
+        .await;
+    let away = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.01, 37.3), station(6, 55.0, 37.5)),
+        )
+        .await;
+
+    (trip, beside, away)
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn nearest_stations_come_closest_first() {
+    let h = Harness::start().await;
+    setup(&h).await;
+
+    let (status, body) = h
+        .get("/routes/stations/nearest?lat=55.0&lon=37.21&limit=2")
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let stations = body["stations"].as_array().unwrap();
+    let ids: Vec<_> = stations
+        .iter()
+        .map(|s| s["station"]["id"].clone())
+        .collect();
+    assert_eq!(ids, [json!(id(3)), json!(id(5))]);
+
+    let distances: Vec<f64> = stations
+        .iter()
+        .map(|s| s["distance"].as_f64().unwrap())
+        .collect();
+    assert!((600.0..700.0).contains(&distances[0]), "{body}");
+    assert!(distances[0] < distances[1], "{body}");
+
+    let (status, body) = h.get("/routes/stations/nearest?lat=91.0&lon=37.0").await;
+    assert_eq!(status, 422, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn corridor_holds_requests_beside_the_road() {
+    let h = Harness::start().await;
+    let (trip, beside, away) = setup(&h).await;
+
+    let (status, body) = h
+        .get(&format!("/routes/trips/{trip}/corridor?width=500"))
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["routeIds"], json!([beside]));
+
+    let (status, body) = h
+        .get(&format!("/routes/trips/{trip}/corridor?width=2000"))
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let mut both = [beside.clone(), away];
+    both.sort();
+    assert_eq!(body["routeIds"], json!(both));
+
+    // Assigned cargo requests are not offered again.
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [beside]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap();
+
+    let (status, body) = h
+        .get(&format!("/routes/trips/{v2}/corridor?width=500"))
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["routeIds"], json!([]));
+
+    let (status, body) = h
+        .get(&format!("/routes/trips/{}/corridor?width=500", id(99)))
+        .await;
+    assert_eq!(status, 404, "{body}");
+}
@@ -0,0 +1,231 @@
+//! Reading, writing, listing and deleting stations on their own.
+
+mod common;
+
+use serde_json::{Value, json};
+
+use common::{Harness, station};
+
+fn id(n: u8) -> String {
+    format!("00000000-0000-0000-0000-{n:012}")
+}
+
+/// Station `n` with the given address.
+fn named(n: u8, address: &str, lat: f64, lon: f64) -> Value {
+    let mut station = station(n, lat, lon);
+    station["address"] = json!(address);
+    station
+}
+
+/// IDs of the listed stations, in order.
+fn listed(body: &Value) -> Vec<String> {
+    body["stations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|station| station["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn stations_are_written_read_and_deleted() {
+    let h = Harness::start().await;
+    let path = format!("/routes/stations/{}", id(1));
+
+    let (status, body) = h.get(&path).await;
+    assert_eq!(status, 404, "{body}");
+
+    let (status, body) = h
+        .put(
+            &path,
+            json!({"address": "depot", "coords": {"lat": 55.0, "lon": 37.0}}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (status, body) = h
+        .put(
+            &path,
+            json!({"address": "new depot", "coords": {"lat": 55.5, "lon": 37.5}}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (status, body) = h.get(&path).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["station"], named(1, "new depot", 55.5, 37.5));
+
+    let (status, body) = h
+        .put(
+            &path,
+            json!({"address": " ", "coords": {"lat": 91.0, "lon": 37.0}}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+    assert_eq!(body["details"].as_array().unwrap().len(), 2, "{body}");
+
+    let (status, body) = h.delete(&path).await;
+    assert_eq!(status, 204, "{body}");
+
+    let (status, body) = h.get(&path).await;
+    assert_eq!(status, 404, "{body}");
+
+    let (status, body) = h.delete(&path).await;
+    assert_eq!(status, 404, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn stations_in_use_are_not_deleted() {
+    let h = Harness::start().await;
+    let route = |from, to| json!({"fromStation": from, "toStation": to});
+    h.create(
+        "/routes/trips",
+        route(station(1, 55.0, 37.0), station(2, 55.0, 38.0)),
+    )
+    .await;
+    h.create(
+        "/routes/cargo_requests",
+        route(station(3, 55.0, 37.2), station(4, 55.0, 37.8)),
+    )
+    .await;
+
+    for n in 1..=4 {
+        let (status, body) = h.delete(&format!("/routes/stations/{}", id(n))).await;
+        assert_eq!(status, 409, "{body}");
+        assert_eq!(body["code"], "conflict");
+    }
+
+    let stored: i64 = sqlx::query_scalar("SELECT count(*) FROM station;")
+        .fetch_one(&h.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, 4);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn moving_a_station_in_use_refetches_its_segments() {
+    let h = Harness::start().await;
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let points = format!("/routes/trips/{trip}/points");
+
+    let (status, body) = h
+        .put(
+            &format!("/routes/stations/{}", id(1)),
+            json!({"address": "station 1", "coords": {"lat": 56.0, "lon": 37.0}}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (_, body) = h.get(&points).await;
+    assert_eq!(body["points"][0], json!([56.0, 37.0]), "{body}");
+
+    let (status, body) = h
+        .post(
+            "/routes/stations/bulk",
+            json!({"stations": [station(2, 56.0, 38.0)]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (_, body) = h.get(&points).await;
+    let last = body["points"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(last, json!([56.0, 38.0]), "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn bulk_upsert_tells_created_updated_and_unchanged() {
+    let h = Harness::start().await;
+    h.post(
+        "/routes/stations/bulk",
+        json!({"stations": [station(1, 55.0, 37.0), station(2, 55.0, 38.0)]}),
+    )
+    .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/stations/bulk",
+            json!({"stations": [
+                station(1, 55.0, 37.0),
+                named(2, "renamed", 55.0, 38.0),
+                station(3, 55.0, 39.0),
+            ]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(
+        body,
+        json!({"created": [id(3)], "updated": [id(2)], "unchanged": [id(1)]})
+    );
+
+    let (status, body) = h
+        .post(
+            "/routes/stations/bulk",
+            json!({"stations": [station(4, 55.0, 40.0), station(4, 55.0, 41.0)]}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+    assert_eq!(body["details"][0]["target"], "stations[1].id");
+
+    let (status, _) = h.get(&format!("/routes/stations/{}", id(4))).await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn list_pages_and_filters_stations() {
+    let h = Harness::start().await;
+    h.post(
+        "/routes/stations/bulk",
+        json!({"stations": [
+            named(1, "a 100% depot", 55.0, 37.0),
+            named(2, "b 1000 depot", 55.5, 37.5),
+            named(3, "c north_gate", 56.0, 38.0),
+            named(4, "d northXgate", 60.0, 30.0),
+            named(5, "e back\\yard", 50.0, 37.2),
+        ]}),
+    )
+    .await;
+
+    let (status, body) = h.get("/routes/stations?limit=2&offset=1").await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["total"], 5);
+    assert_eq!(listed(&body), [id(2), id(3)]);
+
+    let (_, body) = h.get("/routes/stations?limit=2&offset=4").await;
+    assert_eq!(body["total"], 5);
+    assert_eq!(listed(&body), [id(5)]);
+
+    let (_, body) = h
+        .get("/routes/stations?minLat=54.9&minLon=36.9&maxLat=56.1&maxLon=38.1")
+        .await;
+    assert_eq!(body["total"], 3);
+    assert_eq!(listed(&body), [id(1), id(2), id(3)]);
+
+    let (status, body) = h.get("/routes/stations?minLat=54.9&maxLat=56.1").await;
+    assert_eq!(status, 422, "{body}");
+
+    // Wildcards of LIKE match themselves only.
+    for (q, expected) in [
+        ("%25", vec![id(1)]),
+        ("_", vec![id(3)]),
+        ("%5C", vec![id(5)]),
+        ("NORTH", vec![id(3), id(4)]),
+    ] {
+        let (status, body) = h.get(&format!("/routes/stations?q={q}")).await;
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(listed(&body), expected, "q={q}");
+        assert_eq!(body["total"], expected.len(), "q={q}");
+    }
+}
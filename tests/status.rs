@@ -0,0 +1,97 @@
+mod common;
+
+use serde_json::json;
+
+use common::{Harness, station};
+
+/// Creates a trip and merges a cargo request into it. Returns the trip's
+/// first and second versions and the cargo request.
+async fn setup(h: &Harness) -> (String, String, String) {
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(3, 55.0, 37.2),
+                "toStation": station(4, 55.0, 37.8),
+            }),
+        )
+        .await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    (trip, v2, request)
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn only_the_latest_version_changes_status() {
+    let h = Harness::start().await;
+
+    let (v1, v2, _) = setup(&h).await;
+
+    let (status, body) = h
+        .put(
+            &format!("/routes/trips/{v1}/status"),
+            json!({"status": "planned"}),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+
+    let (status, body) = h
+        .put(
+            &format!("/routes/trips/{v2}/status"),
+            json!({"status": "planned"}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn requests_under_way_stay_on_their_trip() {
+    let h = Harness::start().await;
+
+    let (_, v2, request) = setup(&h).await;
+
+    let (status, body) = h
+        .put(
+            &format!("/routes/cargo_requests/{request}/status"),
+            json!({"status": "in_progress"}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/unmerge",
+            json!({"tripRouteId": v2, "cargoRequestRouteIds": [request]}),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/remove_stations",
+            json!({"tripId": v2, "deleteStationIds": ["00000000-0000-0000-0000-000000000003"]}),
+        )
+        .await;
+    assert_eq!(status, 409, "{body}");
+
+    assert_eq!(h.trip_of(&request).await.as_deref(), Some(v2.as_str()));
+}
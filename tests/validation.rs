@@ -0,0 +1,107 @@
+//! Field-level problems of a new route.
+
+use serde_json::json;
+
+use gw_routes::api::service::Error;
+use gw_routes::api::service::types::{CreateRouteRequest, ScoringOptions};
+use gw_routes::api::service::validation::{Validate, check_route};
+
+fn targets(body: serde_json::Value, is_request: bool) -> Vec<String> {
+    let r: CreateRouteRequest = serde_json::from_value(body).unwrap();
+
+    match check_route(&r, is_request) {
+        Ok(()) => Vec::new(),
+        Err(Error::InvalidInput(details)) => details.into_iter().map(|d| d.target).collect(),
+        Err(e) => panic!("unexpected error {e:?}"),
+    }
+}
+
+fn station(n: u128) -> serde_json::Value {
+    json!({
+        "id": uuid::Uuid::from_u128(n),
+        "address": format!("station {n}"),
+        "coords": {"lat": 55.0, "lon": 37.0 + n as f64},
+    })
+}
+
+#[test]
+fn windows_and_amounts_name_their_fields() {
+    let body = json!({
+        "fromStation": station(1),
+        "toStation": station(2),
+        "fromWindow": {"earliest": 200, "latest": 100},
+        "cargo": {"weight": -1.0, "pallets": -2},
+    });
+
+    assert_eq!(
+        targets(body, true),
+        ["fromWindow.latest", "cargo.weight", "cargo.pallets"]
+    );
+}
+
+#[test]
+fn fields_of_the_other_kind_are_rejected() {
+    let body = json!({
+        "fromStation": station(1),
+        "toStation": station(2),
+        "toWindow": {"latest": 100},
+        "capacity": {"volume": -3.0},
+    });
+
+    assert_eq!(targets(body.clone(), true), ["capacity.volume", "capacity"]);
+    assert_eq!(targets(body, false), ["capacity.volume", "toWindow"]);
+}
+
+#[test]
+fn latitude_beyond_the_poles_is_rejected() {
+    let mut from = station(1);
+    from["coords"]["lat"] = json!(90.5);
+    let body = json!({"fromStation": from, "toStation": station(2)});
+
+    assert_eq!(targets(body, false), ["fromStation.coords.lat"]);
+}
+
+#[test]
+fn longitude_beyond_the_date_line_is_rejected() {
+    let mut to = station(2);
+    to["coords"]["lon"] = json!(-180.5);
+    let body = json!({"fromStation": station(1), "toStation": to});
+
+    assert_eq!(targets(body, false), ["toStation.coords.lon"]);
+}
+
+#[test]
+fn blank_address_is_rejected() {
+    for address in ["", "  "] {
+        let mut to = station(2);
+        to["address"] = json!(address);
+        let body = json!({"fromStation": station(1), "toStation": to});
+
+        assert_eq!(targets(body, true), ["toStation.address"], "{address:?}");
+    }
+}
+
+#[test]
+fn route_must_leave_its_station() {
+    let body = json!({"fromStation": station(1), "toStation": station(1)});
+
+    assert_eq!(targets(body, false), ["toStation.id"]);
+}
+
+#[test]
+fn limits_must_be_non_negative_numbers() {
+    let options = ScoringOptions {
+        max_added_distance: Some(-1.0),
+        max_added_time: Some(f64::NAN),
+        max_detour_percent: Some(25.0),
+        ..Default::default()
+    };
+
+    match options.check() {
+        Err(Error::InvalidInput(details)) => {
+            let targets: Vec<_> = details.into_iter().map(|d| d.target).collect();
+            assert_eq!(targets, ["maxAddedDistance", "maxAddedTime"]);
+        }
+        r => panic!("unexpected result {r:?}"),
+    }
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use serde_json::json;
+
+use common::{Harness, station};
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn every_version_leads_to_the_latest() {
+    let h = Harness::start().await;
+
+    let v1 = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+            }),
+        )
+        .await;
+    let request = |from, to| json!({"fromStation": from, "toStation": to});
+    let first = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(3, 55.0, 37.2), station(4, 55.0, 37.4)),
+        )
+        .await;
+    let second = h
+        .create(
+            "/routes/cargo_requests",
+            request(station(5, 55.0, 37.6), station(6, 55.0, 37.8)),
+        )
+        .await;
+
+    let merge =
+        |trip: &str, request: &str| json!({"tripRouteId": trip, "cargoRequestRouteId": [request]});
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&v1, &first)).await;
+    assert_eq!(status, 200, "{body}");
+    let v2 = body["routeId"].as_str().unwrap().to_string();
+
+    let (status, body) = h.post("/routes/trips/merge", merge(&v2, &second)).await;
+    assert_eq!(status, 200, "{body}");
+    let v3 = body["routeId"].as_str().unwrap().to_string();
+
+    for trip in [&v1, &v2, &v3] {
+        let (status, body) = h.get(&format!("/routes/trips/{trip}/latest")).await;
+        assert_eq!(status, 200, "{body}");
+        assert_eq!(body["routeId"], json!(v3));
+        assert_eq!(body["version"], json!(3));
+
+        let (status, body) = h.get(&format!("/routes/trips/{trip}/history")).await;
+        assert_eq!(status, 200, "{body}");
+        let versions = body["versions"].as_array().unwrap();
+        let ids: Vec<_> = versions.iter().map(|v| v["routeId"].clone()).collect();
+        assert_eq!(ids, [json!(v1), json!(v2), json!(v3)]);
+
+        let parents: Vec<_> = versions
+            .iter()
+            .map(|v| v["parentRouteId"].clone())
+            .collect();
+        assert_eq!(parents, [json!(null), json!(v1), json!(v2)]);
+
+        let merged: Vec<_> = versions
+            .iter()
+            .map(|v| v["mergedCargoRequestRouteIds"].clone())
+            .collect();
+        assert_eq!(merged, [json!([]), json!([first]), json!([second])]);
+    }
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use serde_json::{Value, json};
+
+use common::{Harness, station};
+
+const DEPARTURE: i64 = 1_000_000;
+
+/// A trip from 55N 37E to 55N 38E and a cargo request halfway along it that
+/// has to be delivered within `latest` seconds of the departure.
+async fn setup(h: &Harness, latest: i64) -> (String, String) {
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+                "departureTime": DEPARTURE,
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(5, 55.0, 37.5),
+                "toStation": station(6, 55.0, 37.9),
+                "toWindow": {"latest": DEPARTURE + latest},
+            }),
+        )
+        .await;
+
+    (trip, request)
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn windows_are_checked_by_road() {
+    let h = Harness::start().await;
+
+    // About 57 km to the drop-off: in time by great-circle estimate, late by road.
+    let (trip, request) = setup(&h, 5000).await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/potential",
+            json!({"tripRouteId": trip, "cargoRequestRouteIds": [request], "metric": "haversine"}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["candidates"][0]["withinTimeWindows"], false, "{body}");
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 422, "{body}");
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn preview_arrivals_follow_legs() {
+    let h = Harness::start().await;
+
+    let (trip, request) = setup(&h, 6000).await;
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge/preview",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    let legs: Vec<i64> = body["legs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|leg| leg["tripTime"].as_i64().unwrap())
+        .collect();
+    let expected: Vec<Value> = std::iter::once(DEPARTURE)
+        .chain(legs.iter().scan(DEPARTURE, |time, leg| {
+            *time += leg;
+            Some(*time)
+        }))
+        .map(Value::from)
+        .collect();
+
+    assert_eq!(body["stationIds"].as_array().unwrap().len(), expected.len());
+    for (arrival, expected) in body["arrivalTimes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .zip(&expected)
+    {
+        let (arrival, expected) = (arrival.as_i64().unwrap(), expected.as_i64().unwrap());
+        assert!((arrival - expected).abs() <= 1, "{body}");
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a database, see TEST_PG_URL"]
+async fn merges_without_windows_fetch_only_the_new_path() {
+    let h = Harness::start().await;
+
+    let trip = h
+        .create(
+            "/routes/trips",
+            json!({
+                "fromStation": station(1, 55.0, 37.0),
+                "toStation": station(2, 55.0, 38.0),
+                "departureTime": DEPARTURE,
+            }),
+        )
+        .await;
+    let request = h
+        .create(
+            "/routes/cargo_requests",
+            json!({
+                "fromStation": station(5, 55.0, 37.5),
+                "toStation": station(6, 55.0, 37.9),
+            }),
+        )
+        .await;
+    let calls = h.calls.lock().unwrap().len();
+
+    let (status, body) = h
+        .post(
+            "/routes/trips/merge",
+            json!({"tripRouteId": trip, "cargoRequestRouteId": [request]}),
+        )
+        .await;
+    assert_eq!(status, 200, "{body}");
+
+    // 1 -> 5 and 6 -> 2, 5 -> 6 is cached with the cargo request.
+    assert_eq!(h.calls.lock().unwrap().len() - calls, 2);
+}